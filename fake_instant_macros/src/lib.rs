@@ -0,0 +1,220 @@
+// Copyright 2018 MaidSafe.net limited.
+//
+// This SAFE Network Software is licensed to you under the MIT license <LICENSE-MIT
+// http://opensource.org/licenses/MIT> or the Modified BSD license <LICENSE-BSD
+// https://opensource.org/licenses/BSD-3-Clause>, at your option. This file may not be copied,
+// modified, or distributed except according to those terms. Please review the Licences for the
+// specific language governing permissions and limitations relating to use of the SAFE Network
+// Software.
+
+//! # fake_instant_macros
+//!
+//! Procedural macros for `fake_instant`. Use them through the `macros`
+//! feature of `fake_instant` rather than depending on this crate directly.
+
+// For explanation of lint checks, run `rustc -W help`.
+#![forbid(
+    bad_style,
+    arithmetic_overflow,
+    mutable_transmutes,
+    no_mangle_const_items,
+    unknown_crate_types
+)]
+#![deny(
+    missing_docs,
+    overflowing_literals,
+    unsafe_code,
+    warnings,
+    trivial_casts,
+    trivial_numeric_casts,
+    unused_extern_crates,
+    unused_import_braces,
+    unused_qualifications,
+    unused_must_use
+)]
+
+use proc_macro::{Delimiter, Group, Ident, Literal, Punct, Spacing, Span, TokenStream, TokenTree};
+
+/// Marks a function as a test run against a fresh fake clock.
+///
+/// The test starts with a new `FakeClock` made current for the test thread,
+/// so no fake time leaks in from or out to other tests. `async fn` tests are
+/// run with `fake_instant::executor::block_on`, which advances the clock to
+/// the next pending deadline whenever the test is waiting on a timer.
+///
+/// Accepts the following comma-separated options:
+///
+/// - `start = <millis>`: the fake time the test starts at, in milliseconds.
+///   Defaults to zero.
+/// - `global`: runs the test on the process-wide clock in
+///   `ClockMode::Global`, restoring the previous mode and time afterwards.
+///   Such tests share one clock, so must not run concurrently with each
+///   other.
+/// - `auto_advance`: enables auto-advance on the test's clock, with the test
+///   thread registered as a participant.
+///
+/// ```ignore
+/// #[fake_instant::test(start = 1000)]
+/// fn starts_at_one_second() {
+///     assert_eq!(1000, fake_instant::FakeInstant::time());
+/// }
+///
+/// #[fake_instant::test]
+/// async fn sleeps_instantly() {
+///     fake_instant::future::sleep(std::time::Duration::from_secs(3600)).await;
+/// }
+/// ```
+#[proc_macro_attribute]
+pub fn test(args: TokenStream, item: TokenStream) -> TokenStream {
+    match expand_test(args, item) {
+        Ok(tokens) => tokens,
+        Err(error) => error.into_compile_error(),
+    }
+}
+
+/// An error to report at the given span.
+struct Error {
+    span: Span,
+    message: &'static str,
+}
+
+impl Error {
+    fn new(span: Span, message: &'static str) -> Self {
+        Self { span, message }
+    }
+
+    /// Returns a `::core::compile_error!` invocation reporting this error.
+    fn into_compile_error(self) -> TokenStream {
+        let mut message = Literal::string(self.message);
+        message.set_span(self.span);
+        [
+            TokenTree::Punct(Punct::new(':', Spacing::Joint)),
+            TokenTree::Punct(Punct::new(':', Spacing::Alone)),
+            TokenTree::Ident(Ident::new("core", self.span)),
+            TokenTree::Punct(Punct::new(':', Spacing::Joint)),
+            TokenTree::Punct(Punct::new(':', Spacing::Alone)),
+            TokenTree::Ident(Ident::new("compile_error", self.span)),
+            TokenTree::Punct(Punct::new('!', Spacing::Alone)),
+            TokenTree::Group(Group::new(
+                Delimiter::Parenthesis,
+                TokenTree::Literal(message).into(),
+            )),
+            TokenTree::Punct(Punct::new(';', Spacing::Alone)),
+        ]
+        .into_iter()
+        .map(|mut token| {
+            token.set_span(self.span);
+            token
+        })
+        .collect()
+    }
+}
+
+/// Options passed to `#[fake_instant::test(...)]`.
+#[derive(Default)]
+struct TestArgs {
+    start: Option<TokenStream>,
+    global: bool,
+    auto_advance: bool,
+}
+
+fn parse_test_args(args: TokenStream) -> Result<TestArgs, Error> {
+    const EXPECTED: &str = "expected `start = <millis>`, `global` or `auto_advance`";
+
+    let mut parsed = TestArgs::default();
+    let mut tokens = args.into_iter().peekable();
+    while let Some(token) = tokens.next() {
+        let TokenTree::Ident(ident) = &token else {
+            return Err(Error::new(token.span(), EXPECTED));
+        };
+        match ident.to_string().as_str() {
+            "global" => parsed.global = true,
+            "auto_advance" => parsed.auto_advance = true,
+            "start" => {
+                match tokens.next() {
+                    Some(TokenTree::Punct(punct)) if punct.as_char() == '=' => {}
+                    _ => return Err(Error::new(ident.span(), "expected `=` after `start`")),
+                }
+                let mut start = TokenStream::new();
+                while let Some(token) = tokens.next_if(|token| !is_punct(token, ',')) {
+                    start.extend([token]);
+                }
+                if start.is_empty() {
+                    return Err(Error::new(ident.span(), "expected a value for `start`"));
+                }
+                parsed.start = Some(start);
+            }
+            _ => return Err(Error::new(ident.span(), EXPECTED)),
+        }
+        match tokens.next() {
+            None => break,
+            Some(token) if is_punct(&token, ',') => {}
+            Some(token) => return Err(Error::new(token.span(), "expected `,`")),
+        }
+    }
+    Ok(parsed)
+}
+
+fn expand_test(args: TokenStream, item: TokenStream) -> Result<TokenStream, Error> {
+    let args = parse_test_args(args)?;
+
+    let mut signature: Vec<TokenTree> = item.into_iter().collect();
+    let body = match signature.pop() {
+        Some(TokenTree::Group(body)) if body.delimiter() == Delimiter::Brace => body,
+        other => {
+            let span = other.map_or_else(Span::call_site, |token| token.span());
+            return Err(Error::new(span, "expected a function"));
+        }
+    };
+    let fn_index = signature
+        .iter()
+        .position(|token| is_ident(token, "fn"))
+        .ok_or_else(|| Error::new(body.span(), "expected a function"))?;
+    let async_index = signature[..fn_index]
+        .iter()
+        .position(|token| is_ident(token, "async"));
+    if let Some(async_index) = async_index {
+        signature.remove(async_index);
+    }
+
+    let start = args.start.unwrap_or_else(|| "0".parse().unwrap());
+    let mut new_body: TokenStream =
+        "let __fake_instant_test = ::fake_instant::__private::TestClock::new"
+            .parse()
+            .unwrap();
+    new_body.extend([TokenTree::Group(Group::new(
+        Delimiter::Parenthesis,
+        format!(
+            "::core::time::Duration::from_millis({}), {}, {}",
+            start, args.global, args.auto_advance
+        )
+        .parse()
+        .unwrap(),
+    ))]);
+    new_body.extend(";".parse::<TokenStream>().unwrap());
+    if async_index.is_some() {
+        new_body.extend(
+            "::fake_instant::executor::block_on"
+                .parse::<TokenStream>()
+                .unwrap(),
+        );
+        let mut future: TokenStream = "async move".parse().unwrap();
+        future.extend([TokenTree::Group(body)]);
+        new_body.extend([TokenTree::Group(Group::new(Delimiter::Parenthesis, future))]);
+    } else {
+        new_body.extend([TokenTree::Group(body)]);
+    }
+
+    let mut output: TokenStream = "#[::core::prelude::v1::test]".parse().unwrap();
+    output.extend(signature);
+    output.extend([TokenTree::Group(Group::new(Delimiter::Brace, new_body))]);
+    Ok(output)
+}
+
+fn is_ident(token: &TokenTree, name: &str) -> bool {
+    matches!(token, TokenTree::Ident(ident) if ident.to_string() == name)
+}
+
+fn is_punct(token: &TokenTree, ch: char) -> bool {
+    matches!(token, TokenTree::Punct(punct) if punct.as_char() == ch)
+}
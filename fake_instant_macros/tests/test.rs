@@ -0,0 +1,66 @@
+use fake_instant::future::sleep;
+use fake_instant::{ClockMode, FakeClock, FakeInstant};
+use std::sync::{Arc, Barrier};
+use std::time::Duration;
+
+#[fake_instant::test]
+fn starts_at_zero() {
+    assert_eq!(0, FakeInstant::time());
+    FakeInstant::advance_time(500);
+}
+
+#[fake_instant::test(start = 1500)]
+fn starts_at_given_time() {
+    assert_eq!(1500, FakeInstant::time());
+}
+
+#[fake_instant::test]
+fn returns_result() -> Result<(), String> {
+    FakeInstant::advance_time(5);
+    if FakeInstant::time() == 5 {
+        Ok(())
+    } else {
+        Err(format!("unexpected time {}", FakeInstant::time()))
+    }
+}
+
+#[fake_instant::test]
+#[should_panic(expected = "boom")]
+fn keeps_attributes() {
+    panic!("boom");
+}
+
+#[fake_instant::test(start = 10)]
+async fn async_sleep() {
+    let start = FakeInstant::now();
+    sleep(Duration::from_secs(3600)).await;
+    assert_eq!(Duration::from_secs(3600), start.elapsed());
+    assert_eq!(3_600_010, FakeInstant::time());
+}
+
+#[fake_instant::test(global, auto_advance, start = 100)]
+fn global_auto_advance() {
+    assert_eq!(ClockMode::Global, FakeInstant::clock_mode());
+    let registered = Arc::new(Barrier::new(2));
+    let worker = {
+        let registered = registered.clone();
+        std::thread::spawn(move || {
+            let _participant = FakeClock::current().participate();
+            registered.wait();
+            fake_instant::sleep_until(FakeInstant::ORIGIN + Duration::from_millis(60_100));
+            FakeInstant::time()
+        })
+    };
+    registered.wait();
+    fake_instant::sleep(Duration::from_secs(90));
+    assert_eq!(90_100, FakeInstant::time());
+    assert_eq!(60_100, worker.join().unwrap());
+}
+
+#[test]
+fn clock_reset_after_test() {
+    let clock = FakeClock::new();
+    let _guard = clock.enter();
+    starts_at_zero();
+    assert_eq!(0, FakeInstant::time());
+}
@@ -0,0 +1,2126 @@
+// Copyright 2018 MaidSafe.net limited.
+//
+// This SAFE Network Software is licensed to you under the MIT license <LICENSE-MIT
+// http://opensource.org/licenses/MIT> or the Modified BSD license <LICENSE-BSD
+// https://opensource.org/licenses/BSD-3-Clause>, at your option. This file may not be copied,
+// modified, or distributed except according to those terms. Please review the Licences for the
+// specific language governing permissions and limitations relating to use of the SAFE Network
+// Software.
+
+//! Fake clock handles and the selection of the current clock.
+
+use crate::{FakeInstant, FakeSystemTime};
+use std::cell::RefCell;
+use std::collections::{BTreeMap, VecDeque};
+use std::fmt;
+use std::marker::PhantomData;
+use std::panic::Location;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Condvar, Mutex, MutexGuard, OnceLock, PoisonError};
+use std::task::{Poll, Waker};
+use std::thread::{self, ThreadId};
+use std::time::{Duration, Instant};
+
+thread_local! {
+    static THREAD_CLOCK: FakeClock = FakeClock::new();
+    static ENTERED_CLOCK: RefCell<Option<FakeClock>> = const { RefCell::new(None) };
+    /// The clocks this thread participates in, once per `Participant` guard.
+    static PARTICIPATING: RefCell<Vec<FakeClock>> = const { RefCell::new(Vec::new()) };
+}
+
+static GLOBAL_CLOCK: OnceLock<FakeClock> = OnceLock::new();
+static GLOBAL_MODE: AtomicBool = AtomicBool::new(cfg!(feature = "global"));
+/// The real instant that clocks passing through real time count from.
+static REAL_ORIGIN: OnceLock<Instant> = OnceLock::new();
+
+fn real_since_origin() -> Duration {
+    REAL_ORIGIN.get_or_init(Instant::now).elapsed()
+}
+
+/// Selects which fake clock the static `FakeInstant` functions operate on
+/// when no clock has been entered with [`FakeClock::enter`].
+#[derive(Clone, Copy, Debug, Hash, PartialEq, Eq)]
+pub enum ClockMode {
+    /// Each thread has its own fake time, starting at zero. This is the
+    /// default unless the `global` feature is enabled.
+    ThreadLocal,
+    /// All threads share a single process-wide fake time, so advancing it
+    /// from one thread is observed by every other thread.
+    Global,
+}
+
+pub(crate) fn set_mode(mode: ClockMode) -> ClockMode {
+    to_mode(GLOBAL_MODE.swap(mode == ClockMode::Global, Ordering::AcqRel))
+}
+
+pub(crate) fn mode() -> ClockMode {
+    to_mode(GLOBAL_MODE.load(Ordering::Acquire))
+}
+
+fn to_mode(global: bool) -> ClockMode {
+    if global {
+        ClockMode::Global
+    } else {
+        ClockMode::ThreadLocal
+    }
+}
+
+/// Runs `f` with the clock the static `FakeInstant` functions currently
+/// operate on.
+pub(crate) fn with_current<R>(f: impl FnOnce(&FakeClock) -> R) -> R {
+    if let Some(clock) = ENTERED_CLOCK.with(|entered| entered.borrow().clone()) {
+        f(&clock)
+    } else if GLOBAL_MODE.load(Ordering::Acquire) {
+        f(FakeClock::global())
+    } else {
+        THREAD_CLOCK.with(f)
+    }
+}
+
+/// A handle to a fake clock.
+///
+/// Each `FakeClock` tracks its own fake time independently of every other
+/// clock. Cloning a `FakeClock` returns another handle to the same clock, and
+/// handles may be freely shared between threads.
+///
+/// The static `FakeInstant` functions operate on the [current
+/// clock](FakeClock::current): the per-thread clock by default, the global
+/// clock in `ClockMode::Global`, or whichever clock was most recently
+/// [entered](FakeClock::enter) on this thread.
+#[derive(Clone, Default)]
+pub struct FakeClock {
+    inner: Arc<Inner>,
+}
+
+#[derive(Default)]
+struct Inner {
+    state: Mutex<ClockState>,
+    /// Notified whenever the fake time changes.
+    changed: Condvar,
+}
+
+#[derive(Default)]
+pub(crate) struct ClockState {
+    /// The fake time as of the last reading or change.
+    now: Duration,
+    source: Source,
+    /// Every reading of the fake time since recording started.
+    recording: Option<Vec<Duration>>,
+    trace: Option<Trace>,
+    epoch_offset: Duration,
+    /// Pending timers, keyed by deadline and then registration order.
+    timers: BTreeMap<TimerKey, Timer>,
+    next_timer_id: u64,
+    auto_advance: bool,
+    participants: usize,
+    policy: MonotonicPolicy,
+    hooks: Hooks,
+}
+
+/// A pending timer on a clock.
+enum Timer {
+    /// An async timer, woken through its waker.
+    Task(Waker),
+    /// A blocked thread, woken through `Inner::changed`. `participant` is
+    /// `true` if the thread is a participant for auto-advance.
+    Thread { participant: bool },
+    /// A deadline restored from a [`ClockSnapshot`], with nothing to wake.
+    Restored,
+}
+
+/// Callbacks registered on a clock, each with the ID returned on registration.
+#[derive(Default)]
+struct Hooks {
+    next_id: u64,
+    backwards: Vec<(HookId, Hook)>,
+    set: Vec<(HookId, Hook)>,
+    advance: Vec<(HookId, Hook)>,
+}
+
+/// A callback receiving a clock's old and new fake time.
+type Hook = Arc<dyn Fn(Duration, Duration) + Send + Sync>;
+
+/// Identifies a callback registered on a [`FakeClock`], for removal with
+/// [`FakeClock::remove_hook`].
+#[derive(Clone, Copy, Debug, Hash, PartialEq, Eq, PartialOrd, Ord)]
+pub struct HookId(u64);
+
+impl Hooks {
+    fn next_id(&mut self) -> HookId {
+        let id = HookId(self.next_id);
+        self.next_id += 1;
+        id
+    }
+
+    fn remove(&mut self, id: HookId) -> bool {
+        [&mut self.backwards, &mut self.set, &mut self.advance]
+            .into_iter()
+            .any(|hooks| {
+                let len = hooks.len();
+                hooks.retain(|&(hook_id, _)| hook_id != id);
+                hooks.len() != len
+            })
+    }
+}
+
+/// Clones the callbacks in `hooks`, so they can be called without the clock's
+/// lock held.
+fn cloned(hooks: &[(HookId, Hook)]) -> Vec<Hook> {
+    hooks.iter().map(|(_, hook)| hook.clone()).collect()
+}
+
+/// The time changes, and optionally readings, traced since tracing started.
+struct Trace {
+    reads: bool,
+    events: Vec<TraceEvent>,
+}
+
+/// How a clock treats time moving backwards.
+///
+/// The policy applies to [`FakeClock::set`] and `FakeInstant::set_time`, and
+/// to computing a negative duration with `FakeInstant::duration_since`,
+/// `FakeInstant::elapsed` or `FakeInstant - FakeInstant`. For the latter, the
+/// policy of the current clock is used. `checked_duration_since` and
+/// `saturating_duration_since` are never affected.
+///
+/// Restoring the time with [`FakeClock::restore`] or by dropping a
+/// [`TimeGuard`] is exempt, so even under `Strict` a guard can move the time
+/// back; [`FakeClock::on_backwards`] hooks are still called.
+#[derive(Clone, Copy, Debug, Default, Hash, PartialEq, Eq)]
+pub enum MonotonicPolicy {
+    /// Setting the time backwards and computing negative durations both
+    /// panic, naming the two times involved.
+    Strict,
+    /// Setting the time backwards leaves it unchanged, and negative durations
+    /// saturate to zero.
+    Saturating,
+    /// Setting the time backwards is allowed, and negative durations saturate
+    /// to zero. This is the default.
+    #[default]
+    Permissive,
+}
+
+/// Where a clock's fake time comes from.
+#[derive(Default)]
+enum Source {
+    /// The fake time only changes when set or advanced.
+    #[default]
+    Manual,
+    /// The fake time follows the real monotonic clock scaled by `rate`,
+    /// reading `base` at `anchor`.
+    Real {
+        anchor: Instant,
+        base: Duration,
+        rate: f64,
+        paused: bool,
+    },
+    /// Each reading returns the next entry of a recorded log.
+    Replay { log: VecDeque<Duration> },
+}
+
+/// Multiplies `duration` by a finite, non-negative `factor`, saturating at
+/// `Duration::MAX` rather than panicking like `Duration::mul_f64`.
+fn scale(duration: Duration, factor: f64) -> Duration {
+    Duration::try_from_secs_f64(duration.as_secs_f64() * factor).unwrap_or(Duration::MAX)
+}
+
+/// Identifies a timer registered with [`FakeClock::poll_timer`].
+pub(crate) type TimerKey = (Duration, u64);
+
+impl ClockState {
+    /// Brings `now` up to date with a real-time source without counting as a
+    /// reading. Returns `true` if the source is not manual, i.e. if the fake
+    /// time may have changed.
+    fn refresh(&mut self) -> bool {
+        match self.source {
+            Source::Manual => false,
+            Source::Real {
+                anchor,
+                base,
+                rate,
+                paused,
+            } => {
+                if !paused {
+                    self.now = base.saturating_add(scale(anchor.elapsed(), rate));
+                }
+                true
+            }
+            Source::Replay { .. } => true,
+        }
+    }
+
+    /// Reads the fake time on behalf of a user of the clock, consuming a
+    /// replayed reading or recording this one as appropriate.
+    fn read(&mut self) -> Duration {
+        self.refresh();
+        if let Source::Replay { log } = &mut self.source {
+            self.now = log
+                .pop_front()
+                .expect("fake clock read more times than were recorded for replay");
+        }
+        if let Some(recording) = &mut self.recording {
+            recording.push(self.now);
+        }
+        self.now
+    }
+
+    /// Adds an event to the trace, if tracing.
+    fn trace(&mut self, kind: TraceKind, location: &'static Location<'static>) {
+        let time = self.now;
+        if let Some(trace) = &mut self.trace {
+            if kind != TraceKind::Read || trace.reads {
+                trace.events.push(TraceEvent {
+                    kind,
+                    location,
+                    thread: thread::current().id(),
+                    time,
+                });
+            }
+        }
+    }
+
+    /// Sets the fake time, re-anchoring a real-time source so it continues
+    /// from the new time.
+    fn set_now(&mut self, time: Duration) {
+        self.now = time;
+        if let Source::Real { anchor, base, .. } = &mut self.source {
+            *anchor = Instant::now();
+            *base = time;
+        }
+    }
+
+    /// Makes the fake time follow the real monotonic clock at `rate` times
+    /// real speed from its current value, returning the previous rate.
+    fn follow_real_time(&mut self, rate: f64) -> Option<f64> {
+        assert!(
+            rate.is_finite() && rate >= 0.0,
+            "fake clock rate must be finite and non-negative, got {}",
+            rate
+        );
+        self.refresh();
+        let (old_rate, paused) = match self.source {
+            Source::Real { rate, paused, .. } => (Some(rate), paused),
+            Source::Manual | Source::Replay { .. } => (None, false),
+        };
+        self.source = Source::Real {
+            anchor: Instant::now(),
+            base: self.now,
+            rate,
+            paused,
+        };
+        old_rate
+    }
+
+    /// Returns how long to wait in real time for the fake time to reach
+    /// `deadline`, or `None` if only a change to the clock can get it there.
+    fn real_time_until(&self, deadline: Duration) -> Option<Duration> {
+        match self.source {
+            Source::Real {
+                rate,
+                paused: false,
+                ..
+            } if rate > 0.0 => Some(scale(deadline.saturating_sub(self.now), rate.recip())),
+            Source::Real { .. } | Source::Manual | Source::Replay { .. } => None,
+        }
+    }
+
+    /// Registers a timer for `deadline`, returning its key.
+    fn register_timer(&mut self, deadline: Duration, timer: Timer) -> TimerKey {
+        let key = (deadline, self.next_timer_id);
+        self.next_timer_id += 1;
+        self.timers.insert(key, timer);
+        key
+    }
+
+    /// Returns `true` if auto-advance is enabled and every participant is
+    /// blocked in a fake sleep.
+    fn auto_advance_due(&self) -> bool {
+        let sleepers = self
+            .timers
+            .values()
+            .filter(|timer| matches!(timer, Timer::Thread { participant: true }))
+            .count();
+        self.auto_advance && self.participants > 0 && sleepers >= self.participants
+    }
+
+    /// Moves the fake time to the earliest pending deadline, if any, tracing
+    /// the jump as made from `location`. The caller refreshes the fake time
+    /// first.
+    fn jump_to_next(&mut self, location: &'static Location<'static>) -> Option<Duration> {
+        let (&(deadline, _), _) = self.timers.first_key_value()?;
+        self.set_now(self.now.max(deadline));
+        self.trace(TraceKind::Jump, location);
+        Some(self.now)
+    }
+
+    /// Removes and returns all timers whose deadline has been reached.
+    fn take_expired(&mut self) -> BTreeMap<TimerKey, Timer> {
+        let pending = self.timers.split_off(&(self.now, u64::MAX));
+        std::mem::replace(&mut self.timers, pending)
+    }
+}
+
+impl FakeClock {
+    /// Creates a new, independent fake clock starting at zero.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Returns a handle to the clock the static `FakeInstant` functions
+    /// currently operate on.
+    pub fn current() -> Self {
+        with_current(Self::clone)
+    }
+
+    /// Returns a handle to the process-wide clock used in
+    /// `ClockMode::Global`.
+    pub fn global() -> &'static Self {
+        GLOBAL_CLOCK.get_or_init(Self::new)
+    }
+
+    /// Makes this clock the current clock for the calling thread until the
+    /// returned guard is dropped.
+    pub fn enter(&self) -> EnterGuard {
+        let previous = ENTERED_CLOCK.with(|entered| entered.replace(Some(self.clone())));
+        EnterGuard {
+            previous,
+            _not_send: PhantomData,
+        }
+    }
+
+    /// Makes this clock the current clock for the calling thread from now on,
+    /// without a guard.
+    ///
+    /// This is meant for threads that are not spawned by the code setting up
+    /// the clock, such as thread pool workers; see
+    /// [`thread::inherit`](crate::thread::inherit). A clock entered with
+    /// [`FakeClock::enter`] takes precedence until its guard is dropped.
+    pub fn install(&self) {
+        ENTERED_CLOCK.with(|entered| *entered.borrow_mut() = Some(self.clone()));
+    }
+
+    /// Returns a guard that restores this clock's current fake time and epoch
+    /// offset when dropped, including during panic unwinding.
+    ///
+    /// Pending timers are left untouched. As with [`FakeClock::restore`], the
+    /// time is restored regardless of this clock's [`MonotonicPolicy`], and
+    /// hooks are called.
+    #[track_caller]
+    pub fn guard(&self) -> TimeGuard {
+        self.traced_guard(Location::caller())
+    }
+
+    /// Implements [`FakeClock::guard`], tracing the eventual restore as made
+    /// from `location`.
+    pub(crate) fn traced_guard(&self, location: &'static Location<'static>) -> TimeGuard {
+        TimeGuard {
+            clock: self.clone(),
+            snapshot: self.snapshot(),
+            location,
+        }
+    }
+
+    /// Captures this clock's current fake time, epoch offset and pending
+    /// deadlines.
+    pub fn snapshot(&self) -> ClockSnapshot {
+        let mut state = self.lock();
+        state.refresh();
+        ClockSnapshot {
+            now: state.now,
+            epoch_offset: state.epoch_offset,
+            deadlines: state.timers.keys().map(|&(deadline, _)| deadline).collect(),
+        }
+    }
+
+    /// Restores this clock's fake time, epoch offset and pending deadlines
+    /// from `snapshot`, firing any pending timers that are now due.
+    ///
+    /// Each of `snapshot.deadlines` is registered as a timer with nothing to
+    /// wake, so [`FakeClock::next_deadline`], [`FakeClock::advance_to_next`]
+    /// and [`FakeClock::run_until_idle`] stop at it as they would have on the
+    /// snapshotted clock. These replace any deadlines restored earlier. The
+    /// timers of live sleeps and futures on this clock are kept, since their
+    /// owners still wait for them.
+    ///
+    /// The fake time is restored even if that moves it backwards under a
+    /// `Strict` or `Saturating` [`MonotonicPolicy`], but hooks are called as
+    /// for [`FakeClock::set`].
+    #[track_caller]
+    pub fn restore(&self, snapshot: &ClockSnapshot) {
+        self.set_to(TraceKind::Restore, Location::caller(), |state| {
+            state.epoch_offset = snapshot.epoch_offset;
+            state
+                .timers
+                .retain(|_, timer| !matches!(timer, Timer::Restored));
+            for &deadline in &snapshot.deadlines {
+                state.register_timer(deadline, Timer::Restored);
+            }
+            snapshot.now
+        });
+    }
+
+    /// Restores only this clock's fake time and epoch offset from `snapshot`,
+    /// leaving its timers untouched, and traces the restore as made from
+    /// `location`.
+    fn restore_time(&self, snapshot: &ClockSnapshot, location: &'static Location<'static>) {
+        self.set_to(TraceKind::Restore, location, |state| {
+            state.epoch_offset = snapshot.epoch_offset;
+            snapshot.now
+        });
+    }
+
+    /// Returns a `FakeInstant` representing this clock's current fake time.
+    #[track_caller]
+    pub fn now(&self) -> FakeInstant {
+        FakeInstant {
+            time_created: self.since_origin(),
+        }
+    }
+
+    /// Sets this clock's fake time to the given duration since the origin,
+    /// returning the old fake time.
+    ///
+    /// Setting the time backwards is subject to this clock's
+    /// [`MonotonicPolicy`], and calls every hook registered with
+    /// [`FakeClock::on_backwards`] first. Once the time is set, every hook
+    /// registered with [`FakeClock::on_set`] is called.
+    #[track_caller]
+    pub fn set(&self, time: Duration) -> Duration {
+        self.traced_set(time, Location::caller())
+    }
+
+    /// Implements [`FakeClock::set`], tracing the change as made from
+    /// `location`.
+    pub(crate) fn traced_set(
+        &self,
+        time: Duration,
+        location: &'static Location<'static>,
+    ) -> Duration {
+        self.set_to(TraceKind::Set, location, |_| time)
+    }
+
+    /// Sets this clock's fake time to the time returned by `target`, applying
+    /// the [`MonotonicPolicy`] and calling hooks as [`FakeClock::set`] does,
+    /// and traces the change as a `kind` made from `location`.
+    ///
+    /// Restores are exempt from the policy, since moving the time back is
+    /// what snapshots and guards are for.
+    fn set_to(
+        &self,
+        kind: TraceKind,
+        location: &'static Location<'static>,
+        target: impl FnOnce(&mut ClockState) -> Duration,
+    ) -> Duration {
+        let (old, time, policy, backwards, set) = self.update(|state| {
+            state.refresh();
+            let old = state.now;
+            let time = target(state);
+            let policy = match kind {
+                TraceKind::Restore => MonotonicPolicy::Permissive,
+                _ => state.policy,
+            };
+            let backwards = if time >= old {
+                Vec::new()
+            } else {
+                cloned(&state.hooks.backwards)
+            };
+            let set = if time >= old || policy == MonotonicPolicy::Permissive {
+                state.set_now(time);
+                state.trace(kind, location);
+                cloned(&state.hooks.set)
+            } else {
+                Vec::new()
+            };
+            (old, time, policy, backwards, set)
+        });
+        for hook in backwards {
+            hook(old, time);
+        }
+        assert!(
+            time >= old || policy != MonotonicPolicy::Strict,
+            "fake time set backwards from {:?} to {:?}",
+            old,
+            time
+        );
+        for hook in set {
+            hook(old, time);
+        }
+        old
+    }
+
+    /// Sets this clock's [`MonotonicPolicy`], returning the previous policy.
+    pub fn set_policy(&self, policy: MonotonicPolicy) -> MonotonicPolicy {
+        std::mem::replace(&mut self.lock().policy, policy)
+    }
+
+    /// Returns this clock's [`MonotonicPolicy`].
+    pub fn policy(&self) -> MonotonicPolicy {
+        self.lock().policy
+    }
+
+    /// Registers a callback called with the current and requested fake time
+    /// whenever this clock is set or restored backwards, before the clock's
+    /// [`MonotonicPolicy`] is applied.
+    ///
+    /// The callback runs on the thread setting the time, without any lock on
+    /// the clock held.
+    pub fn on_backwards(
+        &self,
+        hook: impl Fn(Duration, Duration) + Send + Sync + 'static,
+    ) -> HookId {
+        let mut state = self.lock();
+        let id = state.hooks.next_id();
+        state.hooks.backwards.push((id, Arc::new(hook)));
+        id
+    }
+
+    /// Registers a callback called with the old and new fake time whenever
+    /// this clock is set with [`FakeClock::set`], including through
+    /// `FakeInstant::set_time`, or restored with [`FakeClock::restore`] or by
+    /// dropping a [`TimeGuard`].
+    ///
+    /// The callback is not called when a backwards set is ignored or rejected
+    /// by the clock's [`MonotonicPolicy`]. Like [`FakeClock::on_backwards`]
+    /// callbacks, it runs on the thread setting the time, without any lock on
+    /// the clock held.
+    pub fn on_set(&self, hook: impl Fn(Duration, Duration) + Send + Sync + 'static) -> HookId {
+        let mut state = self.lock();
+        let id = state.hooks.next_id();
+        state.hooks.set.push((id, Arc::new(hook)));
+        id
+    }
+
+    /// Registers a callback called with the old and new fake time whenever
+    /// this clock is advanced with [`FakeClock::advance`], including through
+    /// `FakeInstant::advance_time`, or jumps to a pending deadline through
+    /// [`FakeClock::advance_to_next`], [`FakeClock::run_until_idle`], an
+    /// executor or auto-advance.
+    ///
+    /// The callback runs on the thread advancing the time, without any lock on
+    /// the clock held.
+    pub fn on_advance(&self, hook: impl Fn(Duration, Duration) + Send + Sync + 'static) -> HookId {
+        let mut state = self.lock();
+        let id = state.hooks.next_id();
+        state.hooks.advance.push((id, Arc::new(hook)));
+        id
+    }
+
+    /// Removes a callback registered on this clock, returning `true` if it was
+    /// found.
+    pub fn remove_hook(&self, id: HookId) -> bool {
+        self.lock().hooks.remove(id)
+    }
+
+    /// Returns the duration from `earlier` to `later`, applying this clock's
+    /// [`MonotonicPolicy`] if it is negative.
+    pub(crate) fn duration_between(&self, later: FakeInstant, earlier: FakeInstant) -> Duration {
+        later.checked_duration_since(earlier).unwrap_or_else(|| {
+            assert!(
+                self.policy() != MonotonicPolicy::Strict,
+                "fake instant {:?} is earlier than {:?}",
+                later,
+                earlier
+            );
+            Duration::ZERO
+        })
+    }
+
+    /// Advances this clock's fake time by the given duration, returns the new
+    /// fake time.
+    ///
+    /// Calls every hook registered with [`FakeClock::on_advance`] afterwards.
+    #[track_caller]
+    pub fn advance(&self, duration: Duration) -> Duration {
+        self.traced_advance(duration, Location::caller())
+    }
+
+    /// Implements [`FakeClock::advance`], tracing the change as made from
+    /// `location`.
+    pub(crate) fn traced_advance(
+        &self,
+        duration: Duration,
+        location: &'static Location<'static>,
+    ) -> Duration {
+        let (old, new_time, hooks) = self.update(|state| {
+            state.refresh();
+            let old = state.now;
+            let new_time = old
+                .checked_add(duration)
+                .expect("overflow when advancing fake time");
+            state.set_now(new_time);
+            state.trace(TraceKind::Advance, location);
+            (old, new_time, cloned(&state.hooks.advance))
+        });
+        for hook in hooks {
+            hook(old, new_time);
+        }
+        new_time
+    }
+
+    /// Applies a change to the fake time, then wakes every sleeping thread and
+    /// every timer whose deadline has been reached.
+    fn update<R>(&self, f: impl FnOnce(&mut ClockState) -> R) -> R {
+        let mut state = self.lock();
+        let result = f(&mut state);
+        self.fire(state);
+        result
+    }
+
+    /// Releases `state` after a change to the fake time, waking every
+    /// sleeping thread and every timer whose deadline has been reached.
+    fn fire(&self, mut state: MutexGuard<'_, ClockState>) {
+        let expired = state.take_expired();
+        drop(state);
+        self.inner.changed.notify_all();
+        for timer in expired.into_values() {
+            if let Timer::Task(waker) = timer {
+                waker.wake();
+            }
+        }
+    }
+
+    /// Enables or disables auto-advance, returning the previous setting.
+    ///
+    /// While enabled, whenever every [participant](FakeClock::participate) is
+    /// blocked in a fake sleep on this clock, the clock automatically jumps to
+    /// the earliest pending deadline. Threads sleeping without participating
+    /// do not count, and neither do async tasks; executors are expected to
+    /// advance the clock themselves.
+    #[track_caller]
+    pub fn set_auto_advance(&self, enabled: bool) -> bool {
+        let mut state = self.lock();
+        let old = std::mem::replace(&mut state.auto_advance, enabled);
+        self.auto_advance_if_due(state, Location::caller());
+        old
+    }
+
+    /// Registers the calling thread as a participant for auto-advance until
+    /// the returned guard is dropped.
+    ///
+    /// To keep the clock from jumping before every worker thread has
+    /// registered, have the workers wait on a `Barrier` after participating.
+    #[track_caller]
+    pub fn participate(&self) -> Participant {
+        self.lock().participants += 1;
+        PARTICIPATING.with(|participating| participating.borrow_mut().push(self.clone()));
+        Participant {
+            clock: self.clone(),
+            location: Location::caller(),
+            _not_send: PhantomData,
+        }
+    }
+
+    /// Returns `true` if the calling thread participates in this clock.
+    fn is_participant(&self) -> bool {
+        PARTICIPATING.with(|participating| {
+            participating
+                .borrow()
+                .iter()
+                .any(|clock| Arc::ptr_eq(&clock.inner, &self.inner))
+        })
+    }
+
+    /// Returns a blocked-thread timer for the calling thread.
+    fn thread_timer(&self) -> Timer {
+        Timer::Thread {
+            participant: self.is_participant(),
+        }
+    }
+
+    fn auto_advance_if_due(
+        &self,
+        state: MutexGuard<'_, ClockState>,
+        location: &'static Location<'static>,
+    ) {
+        if state.auto_advance_due() {
+            self.jump(state, location);
+        }
+    }
+
+    /// Moves the fake time to the earliest pending deadline, if any, tracing
+    /// the jump as made from `location`. Then releases `state`, waking the
+    /// timers that are due, and calls every hook registered with
+    /// [`FakeClock::on_advance`]. Returns the new fake time, or `None` if
+    /// there are no pending timers.
+    fn jump(
+        &self,
+        mut state: MutexGuard<'_, ClockState>,
+        location: &'static Location<'static>,
+    ) -> Option<Duration> {
+        state.refresh();
+        let old = state.now;
+        let new_time = state.jump_to_next(location);
+        let hooks = cloned(&state.hooks.advance);
+        self.fire(state);
+        if let Some(new_time) = new_time {
+            for hook in hooks {
+                hook(old, new_time);
+            }
+        }
+        new_time
+    }
+
+    /// Polls a timer for `deadline`, registering `waker` to be woken once the
+    /// fake time reaches it. `key` tracks the registration between polls.
+    pub(crate) fn poll_timer(
+        &self,
+        deadline: Duration,
+        key: &mut Option<TimerKey>,
+        waker: &Waker,
+    ) -> Poll<()> {
+        let mut state = self.lock();
+        state.refresh();
+        if state.now >= deadline {
+            if let Some(key) = key.take() {
+                state.timers.remove(&key);
+            }
+            return Poll::Ready(());
+        }
+        match key.and_then(|key| state.timers.get_mut(&key)) {
+            Some(Timer::Task(registered)) if registered.will_wake(waker) => {}
+            Some(registered) => *registered = Timer::Task(waker.clone()),
+            None => *key = Some(state.register_timer(deadline, Timer::Task(waker.clone()))),
+        }
+        Poll::Pending
+    }
+
+    /// Polls whether the fake time has reached `deadline` on behalf of a
+    /// thread blocked waiting for it alongside some real event. The thread is
+    /// registered as a sleeper, counting towards auto-advance, until the
+    /// deadline is reached or `key` is cancelled. Auto-advance jumps are
+    /// traced as made from `location`.
+    pub(crate) fn poll_blocking(
+        &self,
+        deadline: Duration,
+        key: &mut Option<TimerKey>,
+        location: &'static Location<'static>,
+    ) -> bool {
+        let timer = self.thread_timer();
+        let mut state = self.lock();
+        let changed = state.refresh();
+        if state.now < deadline && key.is_none() {
+            *key = Some(state.register_timer(deadline, timer));
+        }
+        if state.auto_advance_due() {
+            self.jump(state, location);
+            state = self.lock();
+        } else if changed {
+            self.fire(state);
+            state = self.lock();
+        }
+        if state.now >= deadline {
+            if let Some(key) = key.take() {
+                state.timers.remove(&key);
+            }
+            return true;
+        }
+        false
+    }
+
+    /// Unregisters a timer registered with `poll_timer` or `poll_blocking`.
+    pub(crate) fn cancel_timer(&self, key: &mut Option<TimerKey>) {
+        if let Some(key) = key.take() {
+            self.lock().timers.remove(&key);
+        }
+    }
+
+    /// Returns this clock's fake time as a duration since the origin.
+    #[track_caller]
+    pub fn since_origin(&self) -> Duration {
+        self.traced_since_origin(Location::caller())
+    }
+
+    /// Implements [`FakeClock::since_origin`], tracing the reading as taken
+    /// from `location`.
+    pub(crate) fn traced_since_origin(&self, location: &'static Location<'static>) -> Duration {
+        let mut state = self.lock();
+        let now = state.read();
+        state.trace(TraceKind::Read, location);
+        if state.refresh() {
+            self.fire(state);
+        }
+        now
+    }
+
+    /// Returns the fake time elapsed on this clock since `instant`.
+    ///
+    /// If `instant` is later than this clock's fake time, this returns zero or
+    /// panics, depending on the clock's [`MonotonicPolicy`].
+    #[track_caller]
+    pub fn elapsed(&self, instant: FakeInstant) -> Duration {
+        self.duration_between(self.now(), instant)
+    }
+
+    /// Blocks the calling thread until this clock's fake time has advanced by
+    /// at least `duration`.
+    ///
+    /// Only another thread holding a handle to this clock can advance it, so
+    /// this never returns for a non-zero `duration` unless the clock is shared.
+    #[track_caller]
+    pub fn sleep(&self, duration: Duration) {
+        let deadline = self.since_origin().checked_add(duration);
+        let deadline = deadline.expect("overflow when computing sleep deadline");
+        self.wait_until(deadline, Location::caller());
+    }
+
+    /// Blocks the calling thread until this clock's fake time reaches
+    /// `deadline`.
+    #[track_caller]
+    pub fn sleep_until(&self, deadline: FakeInstant) {
+        self.wait_until(deadline.time_created, Location::caller());
+    }
+
+    /// Blocks the calling thread until this clock's fake time reaches
+    /// `deadline`, tracing any auto-advance jump as made from `location`.
+    pub(crate) fn wait_until(&self, deadline: Duration, location: &'static Location<'static>) {
+        let timer = self.thread_timer();
+        let mut state = self.lock();
+        state.refresh();
+        if let Source::Replay { .. } = state.source {
+            // Replayed time only moves when read, so wake at the reading
+            // recorded when this sleep returned.
+            state.read();
+            return;
+        }
+        if state.now < deadline {
+            let key = state.register_timer(deadline, timer);
+            while state.now < deadline {
+                if state.auto_advance_due() {
+                    self.jump(state, location);
+                    state = self.lock();
+                    continue;
+                }
+                state = match state.real_time_until(deadline) {
+                    Some(timeout) => {
+                        let (mut state, _) = self
+                            .inner
+                            .changed
+                            .wait_timeout(state, timeout)
+                            .unwrap_or_else(PoisonError::into_inner);
+                        if state.refresh() {
+                            self.fire(state);
+                            state = self.lock();
+                        }
+                        state
+                    }
+                    None => self
+                        .inner
+                        .changed
+                        .wait(state)
+                        .unwrap_or_else(PoisonError::into_inner),
+                };
+            }
+            state.timers.remove(&key);
+        }
+        let now = state.now;
+        if let Some(recording) = &mut state.recording {
+            recording.push(now);
+        }
+    }
+
+    /// Returns the earliest deadline of any pending fake sleep or timer
+    /// future on this clock.
+    pub fn next_deadline(&self) -> Option<FakeInstant> {
+        let state = self.lock();
+        let (&(deadline, _), _) = state.timers.first_key_value()?;
+        Some(FakeInstant {
+            time_created: deadline,
+        })
+    }
+
+    /// Advances this clock to the earliest pending deadline, firing every
+    /// timer due at that time. Returns the new fake time, or `None` if there
+    /// are no pending timers.
+    #[track_caller]
+    pub fn advance_to_next(&self) -> Option<FakeInstant> {
+        self.traced_advance_to_next(Location::caller())
+    }
+
+    /// Implements [`FakeClock::advance_to_next`], tracing the jump as made
+    /// from `location`.
+    pub(crate) fn traced_advance_to_next(
+        &self,
+        location: &'static Location<'static>,
+    ) -> Option<FakeInstant> {
+        let time_created = self.jump(self.lock(), location)?;
+        Some(FakeInstant { time_created })
+    }
+
+    /// Repeatedly advances this clock to the earliest pending deadline until
+    /// no timers remain, returning the final fake time.
+    ///
+    /// Timers are fired by waking their sleeper. A woken thread or task that
+    /// registers a new timer before this returns will have it fired as well,
+    /// but one that only does so later, e.g. on its next poll by an executor,
+    /// will not.
+    #[track_caller]
+    pub fn run_until_idle(&self) -> FakeInstant {
+        self.traced_run_until_idle(Location::caller())
+    }
+
+    /// Implements [`FakeClock::run_until_idle`], tracing the jumps and the
+    /// final reading as made from `location`.
+    pub(crate) fn traced_run_until_idle(
+        &self,
+        location: &'static Location<'static>,
+    ) -> FakeInstant {
+        while self.traced_advance_to_next(location).is_some() {}
+        FakeInstant {
+            time_created: self.traced_since_origin(location),
+        }
+    }
+
+    /// Returns a `FakeSystemTime` representing this clock's current fake
+    /// wall-clock time.
+    #[track_caller]
+    pub fn system_now(&self) -> FakeSystemTime {
+        self.traced_system_now(Location::caller())
+    }
+
+    /// Implements [`FakeClock::system_now`], tracing the reading as taken from
+    /// `location`.
+    pub(crate) fn traced_system_now(&self, location: &'static Location<'static>) -> FakeSystemTime {
+        let now = self.traced_since_origin(location);
+        FakeSystemTime::from_since_epoch(
+            self.epoch_offset()
+                .checked_add(now)
+                .expect("overflow when computing fake system time"),
+        )
+    }
+
+    /// Switches this clock to follow the real monotonic clock from its current
+    /// fake time, unless it already does, and starts recording every reading
+    /// of it.
+    ///
+    /// Readings are taken by `now`, `since_origin`, `elapsed`, `system_now`
+    /// and the corresponding static `FakeInstant` and `FakeSystemTime`
+    /// functions, and the time at which each blocking fake sleep returns is
+    /// recorded as a reading too. `set` and `advance` still work, shifting
+    /// the clock relative to real time. Pass the recording to [`FakeClock::replay`] to reproduce
+    /// the same sequence of readings later.
+    pub fn record(&self) {
+        let mut state = self.lock();
+        if !matches!(state.source, Source::Real { .. }) {
+            state.follow_real_time(1.0);
+        }
+        state.recording = Some(Vec::new());
+    }
+
+    /// Stops recording and [freezes](FakeClock::freeze) the clock at its
+    /// current fake time, returning every reading recorded since
+    /// [`FakeClock::record`].
+    pub fn stop_recording(&self) -> Vec<Duration> {
+        self.freeze();
+        self.lock().recording.take().unwrap_or_default()
+    }
+
+    /// Starts tracing every change to this clock's fake time made through
+    /// `set` or `advance`, or the corresponding static `FakeInstant`
+    /// functions, along with every reading taken through `now` or
+    /// `since_origin` if `reads` is `true`. Any previous trace is discarded.
+    ///
+    /// The trace stops when the returned guard is dropped, which prints it to
+    /// standard error if the thread is panicking, e.g. because a test failed.
+    pub fn trace(&self, reads: bool) -> TraceGuard {
+        self.lock().trace = Some(Trace {
+            reads,
+            events: Vec::new(),
+        });
+        TraceGuard {
+            clock: self.clone(),
+        }
+    }
+
+    /// Returns the events traced so far, in the order they happened, or an
+    /// empty timeline if this clock is not being traced.
+    pub fn timeline(&self) -> Vec<TraceEvent> {
+        self.lock()
+            .trace
+            .as_ref()
+            .map(|trace| trace.events.clone())
+            .unwrap_or_default()
+    }
+
+    /// Stops tracing, returning the events traced since [`FakeClock::trace`].
+    pub fn stop_trace(&self) -> Vec<TraceEvent> {
+        self.lock()
+            .trace
+            .take()
+            .map(|trace| trace.events)
+            .unwrap_or_default()
+    }
+
+    /// Makes this clock follow the real monotonic clock at `rate` times real
+    /// speed, e.g. `60.0` for a minute of fake time per real second, starting
+    /// from its current fake time. Returns the previous rate, or `None` if the
+    /// clock was not following real time.
+    ///
+    /// The rate may be changed at any time without the fake time jumping.
+    /// `set` and `advance` still work, shifting the clock relative to real
+    /// time. At very high rates, the fake time saturates at `Duration::MAX`
+    /// since the origin.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `rate` is negative, infinite or NaN.
+    pub fn set_rate(&self, rate: f64) -> Option<f64> {
+        self.update(|state| state.follow_real_time(rate))
+    }
+
+    /// Returns the rate at which this clock follows real time, or `None` if it
+    /// does not.
+    pub fn rate(&self) -> Option<f64> {
+        match self.lock().source {
+            Source::Real { rate, .. } => Some(rate),
+            Source::Manual | Source::Replay { .. } => None,
+        }
+    }
+
+    /// Pauses a clock following real time, keeping its rate for
+    /// [`FakeClock::resume`]. Has no effect on other clocks.
+    pub fn pause(&self) {
+        self.set_paused(true);
+    }
+
+    /// Resumes a clock paused with [`FakeClock::pause`] from the fake time at
+    /// which it was paused.
+    pub fn resume(&self) {
+        self.set_paused(false);
+    }
+
+    fn set_paused(&self, pause: bool) {
+        self.update(|state| {
+            state.refresh();
+            let now = state.now;
+            if let Source::Real { paused, .. } = &mut state.source {
+                *paused = pause;
+                state.set_now(now);
+            }
+        })
+    }
+
+    /// Makes this clock pass through real time: it reads the real monotonic
+    /// time elapsed since a process-wide real origin, plus `offset`.
+    ///
+    /// All clocks passing through real time agree with each other up to their
+    /// offsets. `advance` and `set` adjust the offset, allowing a running
+    /// program to be moved forward in time without pausing it. To do so for
+    /// the whole program, pass the global clock through and switch to
+    /// `ClockMode::Global`:
+    ///
+    /// ```
+    /// use fake_instant::{ClockMode, FakeClock, FakeInstant};
+    /// use std::time::Duration;
+    ///
+    /// FakeClock::global().pass_through(Duration::ZERO);
+    /// FakeInstant::set_clock_mode(ClockMode::Global);
+    /// // Later, e.g. from a debug console:
+    /// FakeInstant::advance(Duration::from_secs(3 * 3600));
+    /// ```
+    ///
+    /// The change of fake time is applied as by [`FakeClock::set`]: moving
+    /// backwards is subject to the clock's [`MonotonicPolicy`], and the
+    /// `on_backwards` and `on_set` hooks are called. If the policy keeps the
+    /// clock from moving backwards, it passes through real time from its
+    /// current fake time instead.
+    #[track_caller]
+    pub fn pass_through(&self, offset: Duration) {
+        self.set_to(TraceKind::Set, Location::caller(), |state| {
+            state.source = Source::Real {
+                anchor: Instant::now(),
+                base: state.now,
+                rate: 1.0,
+                paused: false,
+            };
+            real_since_origin() + offset
+        });
+    }
+
+    /// Returns how far this clock is ahead of the real time that clocks
+    /// [passing through](FakeClock::pass_through) real time read, or `None` if
+    /// it is behind or does not follow real time.
+    pub fn offset(&self) -> Option<Duration> {
+        let mut state = self.lock();
+        if !matches!(state.source, Source::Real { .. }) {
+            return None;
+        }
+        let real = real_since_origin();
+        state.refresh();
+        state.now.checked_sub(real)
+    }
+
+    /// Sets how far this clock is ahead of real time, as for
+    /// [`FakeClock::pass_through`], returning the previous offset.
+    #[track_caller]
+    pub fn set_offset(&self, offset: Duration) -> Option<Duration> {
+        let old = self.offset();
+        self.pass_through(offset);
+        old
+    }
+
+    /// Stops following real time or replaying readings, freezing the clock at
+    /// its current fake time.
+    pub fn freeze(&self) {
+        let mut state = self.lock();
+        state.refresh();
+        state.source = Source::Manual;
+    }
+
+    /// Makes each subsequent reading of this clock return the next entry of
+    /// `readings`, as recorded by [`FakeClock::record`].
+    ///
+    /// A blocking fake sleep returns at once, moving the clock to the reading
+    /// recorded when it returned. Reading the clock more times than there are
+    /// entries panics, since the code under test has diverged from the
+    /// recorded run.
+    pub fn replay(&self, readings: impl IntoIterator<Item = Duration>) {
+        self.lock().source = Source::Replay {
+            log: readings.into_iter().collect(),
+        };
+    }
+
+    /// Stops replaying and freezes the clock at the last replayed reading,
+    /// returning the readings that were not consumed.
+    pub fn stop_replay(&self) -> Vec<Duration> {
+        let mut state = self.lock();
+        match std::mem::take(&mut state.source) {
+            Source::Replay { log } => log.into(),
+            source => {
+                state.source = source;
+                Vec::new()
+            }
+        }
+    }
+
+    /// Sets the wall-clock time corresponding to a fake time of zero on this
+    /// clock, as a duration since `UNIX_EPOCH`. Returns the old offset.
+    pub fn set_epoch_offset(&self, offset: Duration) -> Duration {
+        std::mem::replace(&mut self.lock().epoch_offset, offset)
+    }
+
+    /// Returns this clock's epoch offset.
+    pub fn epoch_offset(&self) -> Duration {
+        self.lock().epoch_offset
+    }
+
+    pub(crate) fn lock(&self) -> MutexGuard<'_, ClockState> {
+        self.inner
+            .state
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+impl fmt::Debug for FakeClock {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut state = self.lock();
+        state.refresh();
+        f.debug_struct("FakeClock")
+            .field("now", &state.now)
+            .finish()
+    }
+}
+
+/// A point-in-time copy of a clock's state, returned by
+/// [`FakeClock::snapshot`].
+///
+/// All fields are plain durations since the clock's origin (or, for
+/// `epoch_offset`, since `UNIX_EPOCH`), so the snapshot can be stored and
+/// compared without reference to any live clock. With the `serde` feature,
+/// it is serialized as a struct with these three fields.
+#[derive(Clone, Debug, Default, Hash, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct ClockSnapshot {
+    /// The fake time.
+    pub now: Duration,
+    /// The wall-clock time corresponding to a fake time of zero.
+    pub epoch_offset: Duration,
+    /// Deadlines of the pending timers, in firing order.
+    pub deadlines: Vec<Duration>,
+}
+
+/// Guard returned by [`FakeClock::guard`] and [`FakeInstant::scoped_time`],
+/// restoring the clock's previous fake time when dropped.
+#[must_use = "the fake time is restored as soon as the guard is dropped"]
+#[derive(Debug)]
+pub struct TimeGuard {
+    clock: FakeClock,
+    snapshot: ClockSnapshot,
+    /// Where the guard was created, to which its restore is traced.
+    location: &'static Location<'static>,
+}
+
+impl Drop for TimeGuard {
+    fn drop(&mut self) {
+        self.clock.restore_time(&self.snapshot, self.location);
+    }
+}
+
+/// Guard returned by [`FakeClock::trace`] and [`FakeInstant::trace`], stopping
+/// the trace when dropped and printing it if the thread is panicking.
+#[must_use = "tracing stops as soon as the guard is dropped"]
+#[derive(Debug)]
+pub struct TraceGuard {
+    clock: FakeClock,
+}
+
+impl TraceGuard {
+    /// Returns the events traced so far.
+    pub fn timeline(&self) -> Vec<TraceEvent> {
+        self.clock.timeline()
+    }
+}
+
+impl Drop for TraceGuard {
+    fn drop(&mut self) {
+        let timeline = self.clock.stop_trace();
+        if thread::panicking() {
+            eprintln!("fake time trace:");
+            for event in timeline {
+                eprintln!("  {}", event);
+            }
+        }
+    }
+}
+
+/// A change to or reading of a traced clock's fake time.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct TraceEvent {
+    /// What happened to the fake time.
+    pub kind: TraceKind,
+    /// Where the time was changed or read.
+    pub location: &'static Location<'static>,
+    /// The thread that changed or read the time.
+    pub thread: ThreadId,
+    /// The fake time afterwards.
+    pub time: Duration,
+}
+
+impl fmt::Display for TraceEvent {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{:?} to {:?} at {} on {:?}",
+            self.kind, self.time, self.location, self.thread
+        )
+    }
+}
+
+/// The kind of a [`TraceEvent`].
+#[derive(Clone, Copy, Debug, Hash, PartialEq, Eq)]
+pub enum TraceKind {
+    /// The time was set.
+    Set,
+    /// The time was advanced.
+    Advance,
+    /// The time was read.
+    Read,
+    /// The time jumped to the next pending deadline, through
+    /// `advance_to_next`, `run_until_idle`, an executor or auto-advance.
+    Jump,
+    /// The time was restored from a snapshot or by a guard.
+    Restore,
+}
+
+/// Clock setup for `#[fake_instant::test]`, undone when dropped.
+#[doc(hidden)]
+pub struct TestClock {
+    clock: FakeClock,
+    previous_mode: Option<ClockMode>,
+    previous_auto_advance: bool,
+    _participant: Option<Participant>,
+    _enter: EnterGuard,
+    _time: TimeGuard,
+}
+
+impl TestClock {
+    #[track_caller]
+    pub fn new(start: Duration, global: bool, auto_advance: bool) -> Self {
+        let clock = if global {
+            FakeClock::global().clone()
+        } else {
+            FakeClock::new()
+        };
+        let previous_mode = global.then(|| set_mode(ClockMode::Global));
+        let time = clock.guard();
+        clock.restore_time(
+            &ClockSnapshot {
+                now: start,
+                ..time.snapshot.clone()
+            },
+            Location::caller(),
+        );
+        let enter = clock.enter();
+        let previous_auto_advance = clock.set_auto_advance(auto_advance);
+        Self {
+            _participant: auto_advance.then(|| clock.participate()),
+            clock,
+            previous_mode,
+            previous_auto_advance,
+            _enter: enter,
+            _time: time,
+        }
+    }
+}
+
+impl Drop for TestClock {
+    fn drop(&mut self) {
+        self.clock.set_auto_advance(self.previous_auto_advance);
+        if let Some(mode) = self.previous_mode {
+            set_mode(mode);
+        }
+    }
+}
+
+/// Guard returned by [`FakeClock::participate`], deregistering the calling
+/// thread as a participant when dropped.
+#[must_use = "the participant is deregistered when the guard is dropped"]
+pub struct Participant {
+    clock: FakeClock,
+    /// Where the guard was created, to which a jump on dropping it is traced.
+    location: &'static Location<'static>,
+    _not_send: PhantomData<*const ()>,
+}
+
+impl Drop for Participant {
+    fn drop(&mut self) {
+        PARTICIPATING.with(|participating| {
+            let mut participating = participating.borrow_mut();
+            let index = participating
+                .iter()
+                .position(|clock| Arc::ptr_eq(&clock.inner, &self.clock.inner));
+            if let Some(index) = index {
+                participating.remove(index);
+            }
+        });
+        let mut state = self.clock.lock();
+        state.participants -= 1;
+        self.clock.auto_advance_if_due(state, self.location);
+    }
+}
+
+/// Guard returned by [`FakeClock::enter`], restoring the previously current
+/// clock when dropped.
+#[must_use = "the clock is only current until the guard is dropped"]
+pub struct EnterGuard {
+    previous: Option<FakeClock>,
+    _not_send: PhantomData<*const ()>,
+}
+
+impl Drop for EnterGuard {
+    fn drop(&mut self) {
+        let previous = self.previous.take();
+        ENTERED_CLOCK.with(|entered| *entered.borrow_mut() = previous);
+    }
+}
+
+impl fmt::Debug for Participant {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Participant")
+            .field("clock", &self.clock)
+            .finish_non_exhaustive()
+    }
+}
+
+impl fmt::Debug for EnterGuard {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("EnterGuard").finish_non_exhaustive()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::future::Future;
+    use std::sync::Barrier;
+
+    #[test]
+    fn test_independent_clocks() {
+        let _clock = FakeClock::new().enter();
+        let a = FakeClock::new();
+        let b = FakeClock::new();
+        let inst_a = a.now();
+        a.advance(Duration::from_millis(100));
+        b.set(Duration::from_secs(7));
+
+        assert_eq!(Duration::from_millis(100), a.elapsed(inst_a));
+        assert_eq!(Duration::from_secs(7), b.since_origin());
+        assert_eq!(Duration::ZERO, FakeInstant::since_origin());
+    }
+
+    #[test]
+    fn test_clone_shares_time() {
+        let a = FakeClock::new();
+        let b = a.clone();
+        thread::spawn(move || b.advance(Duration::from_secs(1)))
+            .join()
+            .unwrap();
+        assert_eq!(Duration::from_secs(1), a.since_origin());
+    }
+
+    #[test]
+    fn test_sleep() {
+        let clock = FakeClock::new();
+        let deadline = clock.now() + Duration::from_secs(10);
+        let sleeper = {
+            let clock = clock.clone();
+            thread::spawn(move || {
+                let _guard = clock.enter();
+                crate::sleep_until(deadline);
+                FakeInstant::now()
+            })
+        };
+        for _ in 0..20 {
+            clock.advance(Duration::from_secs(1));
+            thread::yield_now();
+        }
+        assert!(sleeper.join().unwrap() >= deadline);
+    }
+
+    #[test]
+    fn test_sleep_until_past() {
+        let clock = FakeClock::new();
+        clock.set(Duration::from_secs(2));
+        clock.sleep_until(clock.now() - Duration::from_secs(1));
+        clock.sleep(Duration::ZERO);
+    }
+
+    #[test]
+    fn test_advance_to_next() {
+        let clock = FakeClock::new();
+        let _guard = clock.enter();
+        let waker = Waker::noop();
+        let mut late = crate::future::sleep(Duration::from_millis(300));
+        let mut early = crate::future::sleep(Duration::from_millis(100));
+        let mut cx = std::task::Context::from_waker(waker);
+        assert!(std::pin::Pin::new(&mut late).poll(&mut cx).is_pending());
+        assert!(std::pin::Pin::new(&mut early).poll(&mut cx).is_pending());
+
+        let start = FakeInstant::now();
+        assert_eq!(
+            Some(start + Duration::from_millis(100)),
+            clock.next_deadline()
+        );
+        assert_eq!(
+            Some(start + Duration::from_millis(100)),
+            clock.advance_to_next()
+        );
+        assert_eq!(
+            Some(start + Duration::from_millis(300)),
+            clock.next_deadline()
+        );
+        assert_eq!(start + Duration::from_millis(300), clock.run_until_idle());
+        assert_eq!(None, clock.next_deadline());
+        assert_eq!(None, clock.advance_to_next());
+    }
+
+    #[test]
+    fn test_advance_to_next_sleeper() {
+        let clock = FakeClock::new();
+        let sleeper = {
+            let clock = clock.clone();
+            thread::spawn(move || clock.sleep(Duration::from_secs(30)))
+        };
+        while clock.next_deadline().is_none() {
+            thread::yield_now();
+        }
+        assert_eq!(Duration::from_secs(30), clock.run_until_idle().time_created);
+        sleeper.join().unwrap();
+    }
+
+    #[test]
+    fn test_auto_advance() {
+        let clock = FakeClock::new();
+        clock.set_auto_advance(true);
+        let barrier = Arc::new(Barrier::new(3));
+        let workers: Vec<_> = (1..=3)
+            .map(|i| {
+                let clock = clock.clone();
+                let barrier = barrier.clone();
+                thread::spawn(move || {
+                    let _participant = clock.participate();
+                    barrier.wait();
+                    for _ in 0..10 {
+                        clock.sleep(Duration::from_secs(i * 3600));
+                    }
+                    clock.since_origin()
+                })
+            })
+            .collect();
+        let ends: Vec<_> = workers.into_iter().map(|w| w.join().unwrap()).collect();
+        assert_eq!(
+            vec![
+                Duration::from_secs(10 * 3600),
+                Duration::from_secs(20 * 3600),
+                Duration::from_secs(30 * 3600),
+            ],
+            ends
+        );
+    }
+
+    #[test]
+    fn test_auto_advance_ignores_other_sleepers() {
+        let clock = FakeClock::new();
+        clock.set_auto_advance(true);
+        let _participant = clock.participate();
+        let sleeper = {
+            let clock = clock.clone();
+            thread::spawn(move || clock.sleep(Duration::from_secs(3600)))
+        };
+        while clock.next_deadline().is_none() && !sleeper.is_finished() {
+            thread::yield_now();
+        }
+        assert_eq!(Duration::ZERO, clock.since_origin());
+        clock.sleep(Duration::from_secs(1));
+        assert_eq!(Duration::from_secs(1), clock.since_origin());
+        clock.advance(Duration::from_secs(3600));
+        sleeper.join().unwrap();
+    }
+
+    #[test]
+    fn test_guard_restores_on_panic() {
+        let clock = FakeClock::new();
+        clock.set(Duration::from_secs(1));
+        let result = std::panic::catch_unwind(|| {
+            let _guard = clock.guard();
+            clock.advance(Duration::from_secs(5));
+            clock.set_epoch_offset(Duration::from_secs(9));
+            panic!("test panic");
+        });
+        assert!(result.is_err());
+        assert_eq!(Duration::from_secs(1), clock.since_origin());
+        assert_eq!(Duration::ZERO, clock.epoch_offset());
+    }
+
+    #[test]
+    fn test_snapshot_restore() {
+        let clock = FakeClock::new();
+        clock.set(Duration::from_secs(3));
+        clock.set_epoch_offset(Duration::from_secs(100));
+        let _guard = clock.enter();
+        let mut sleep = crate::future::sleep(Duration::from_secs(2));
+        let mut cx = std::task::Context::from_waker(Waker::noop());
+        assert!(std::pin::Pin::new(&mut sleep).poll(&mut cx).is_pending());
+
+        let snapshot = clock.snapshot();
+        assert_eq!(
+            ClockSnapshot {
+                now: Duration::from_secs(3),
+                epoch_offset: Duration::from_secs(100),
+                deadlines: vec![Duration::from_secs(5)],
+            },
+            snapshot
+        );
+
+        let other = FakeClock::new();
+        other.restore(&snapshot);
+        assert_eq!(other.now(), FakeInstant::ORIGIN + snapshot.now);
+        assert_eq!(FakeSystemTime::now(), other.system_now());
+        assert_eq!(snapshot, other.snapshot());
+        assert_eq!(
+            Some(FakeInstant::ORIGIN + Duration::from_secs(5)),
+            other.advance_to_next()
+        );
+        assert_eq!(None, other.next_deadline());
+
+        other.restore(&snapshot);
+        other.restore(&ClockSnapshot::default());
+        assert_eq!(None, other.next_deadline());
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn test_snapshot_serde() {
+        let clock = FakeClock::new();
+        clock.set(Duration::from_secs(3));
+        clock.set_epoch_offset(Duration::from_secs(100));
+        clock.restore(&ClockSnapshot {
+            deadlines: vec![Duration::from_secs(5)],
+            ..clock.snapshot()
+        });
+
+        let json = serde_json::to_string(&clock.snapshot()).unwrap();
+        let snapshot: ClockSnapshot = serde_json::from_str(&json).unwrap();
+        assert_eq!(clock.snapshot(), snapshot);
+        let other = FakeClock::new();
+        other.restore(&snapshot);
+        assert_eq!(Duration::from_secs(3), other.since_origin());
+        assert_eq!(
+            Some(FakeInstant::ORIGIN + Duration::from_secs(5)),
+            other.advance_to_next()
+        );
+    }
+
+    #[test]
+    fn test_record_replay() {
+        let clock = FakeClock::new();
+        clock.set(Duration::from_secs(1));
+        clock.record();
+        let first = clock.now();
+        thread::sleep(Duration::from_millis(2));
+        let second = clock.now();
+        clock.advance(Duration::from_secs(60));
+        let third = clock.since_origin();
+        let readings = clock.stop_recording();
+
+        assert!(first.time_created >= Duration::from_secs(1));
+        assert!(second - first >= Duration::from_millis(2));
+        assert!(third >= second.time_created + Duration::from_secs(60));
+        assert_eq!(
+            vec![first.time_created, second.time_created, third],
+            readings
+        );
+
+        let replayed = FakeClock::new();
+        replayed.replay(readings.clone());
+        assert_eq!(first, replayed.now());
+        assert_eq!(second - first, replayed.elapsed(first));
+        assert_eq!(vec![third], replayed.stop_replay());
+        assert_eq!(second.time_created, replayed.since_origin());
+    }
+
+    #[test]
+    fn test_debug_does_not_read() {
+        let clock = FakeClock::new();
+        clock.replay([Duration::from_secs(1), Duration::from_secs(2)]);
+        let _trace = clock.trace(true);
+        assert_eq!("FakeClock { now: 0ns }", format!("{:?}", clock));
+        assert_eq!(Duration::from_secs(1), clock.since_origin());
+        assert_eq!(1, clock.timeline().len());
+    }
+
+    #[test]
+    #[should_panic(expected = "read more times than were recorded")]
+    fn test_replay_exhausted() {
+        let clock = FakeClock::new();
+        clock.replay([Duration::from_secs(1)]);
+        clock.now();
+        clock.now();
+    }
+
+    #[test]
+    fn test_recorded_sleep() {
+        let clock = FakeClock::new();
+        clock.record();
+        clock.sleep(Duration::from_millis(5));
+        assert!(clock.since_origin() >= Duration::from_millis(5));
+    }
+
+    #[test]
+    fn test_replayed_sleep() {
+        let clock = FakeClock::new();
+        clock.record();
+        clock.sleep(Duration::from_millis(5));
+        let woken = clock.since_origin();
+        let readings = clock.stop_recording();
+        assert_eq!(3, readings.len());
+
+        let replayed = FakeClock::new();
+        replayed.replay(readings);
+        let start = Instant::now();
+        replayed.sleep(Duration::from_millis(5));
+        assert_eq!(woken, replayed.since_origin());
+        assert!(start.elapsed() < Duration::from_secs(1));
+        assert!(replayed.stop_replay().is_empty());
+    }
+
+    #[test]
+    fn test_scaled_saturates() {
+        let clock = FakeClock::new();
+        clock.set_rate(1e300);
+        thread::sleep(Duration::from_millis(1));
+        assert_eq!(Duration::MAX, clock.since_origin());
+
+        clock.set_rate(1e-300);
+        clock.set(Duration::ZERO);
+        let sleeper = {
+            let clock = clock.clone();
+            thread::spawn(move || clock.sleep(Duration::from_secs(1)))
+        };
+        while clock.next_deadline().is_none() && !sleeper.is_finished() {
+            thread::yield_now();
+        }
+        clock.advance(Duration::from_secs(1));
+        sleeper.join().unwrap();
+    }
+
+    #[test]
+    fn test_scaled() {
+        let clock = FakeClock::new();
+        assert_eq!(None, clock.set_rate(1000.0));
+        thread::sleep(Duration::from_millis(5));
+        let scaled = clock.since_origin();
+        assert!(scaled >= Duration::from_secs(5));
+
+        clock.pause();
+        let paused = clock.since_origin();
+        thread::sleep(Duration::from_millis(2));
+        assert_eq!(paused, clock.since_origin());
+        clock.advance(Duration::from_secs(1));
+        assert_eq!(paused + Duration::from_secs(1), clock.since_origin());
+
+        assert_eq!(Some(1000.0), clock.set_rate(0.5));
+        clock.resume();
+        assert_eq!(Some(0.5), clock.rate());
+        assert!(clock.since_origin() >= paused + Duration::from_secs(1));
+
+        clock.freeze();
+        assert_eq!(None, clock.rate());
+        let frozen = clock.since_origin();
+        thread::sleep(Duration::from_millis(2));
+        assert_eq!(frozen, clock.since_origin());
+    }
+
+    #[test]
+    fn test_scaled_sleep() {
+        let clock = FakeClock::new();
+        clock.set_rate(3600.0);
+        let start = Instant::now();
+        clock.sleep(Duration::from_secs(360));
+        assert!(start.elapsed() < Duration::from_secs(60));
+    }
+
+    #[test]
+    fn test_pass_through() {
+        // Re-anchoring a real-time source loses a few nanoseconds each time.
+        let tolerance = Duration::from_millis(100);
+        let a = FakeClock::new();
+        let b = FakeClock::new();
+        assert_eq!(None, a.offset());
+        a.pass_through(Duration::ZERO);
+        b.pass_through(Duration::from_secs(60));
+        let (a_now, b_now) = (a.since_origin(), b.since_origin());
+        assert!(b_now - a_now + tolerance > Duration::from_secs(60));
+        assert!(b_now - a_now < Duration::from_secs(60) + tolerance);
+
+        b.advance(Duration::from_secs(3600));
+        let offset = b.offset().unwrap();
+        assert!(offset + tolerance > Duration::from_secs(3660));
+        assert!(offset < Duration::from_secs(3660) + tolerance);
+        assert!(b.set_offset(Duration::from_secs(1)).unwrap() + tolerance > offset);
+        assert!(b.offset().unwrap() < Duration::from_secs(1) + tolerance);
+
+        let before = b.since_origin();
+        thread::sleep(Duration::from_millis(2));
+        assert!(b.since_origin() >= before + Duration::from_millis(2));
+    }
+
+    #[test]
+    fn test_pass_through_policy() {
+        let clock = FakeClock::new();
+        clock.set(Duration::from_secs(3600));
+        let seen = Arc::new(Mutex::new(Vec::new()));
+        {
+            let seen = seen.clone();
+            clock.on_backwards(move |old, _| seen.lock().unwrap().push(("backwards", old)));
+        }
+        {
+            let seen = seen.clone();
+            clock.on_set(move |old, _| seen.lock().unwrap().push(("set", old)));
+        }
+        clock.set_policy(MonotonicPolicy::Saturating);
+        clock.set_offset(Duration::ZERO);
+        assert!(clock.since_origin() >= Duration::from_secs(3600));
+        assert_eq!(
+            vec![("backwards", Duration::from_secs(3600))],
+            *seen.lock().unwrap()
+        );
+
+        clock.set_policy(MonotonicPolicy::Strict);
+        let result = std::panic::catch_unwind(|| clock.set_offset(Duration::ZERO));
+        assert!(result.is_err());
+        assert!(clock.since_origin() >= Duration::from_secs(3600));
+
+        clock.pass_through(Duration::from_secs(7200));
+        assert!(clock.since_origin() >= Duration::from_secs(7200));
+        let (kind, old) = *seen.lock().unwrap().last().unwrap();
+        assert_eq!("set", kind);
+        assert!(old >= Duration::from_secs(3600));
+    }
+
+    #[test]
+    #[should_panic(expected = "must be finite and non-negative")]
+    fn test_negative_rate() {
+        FakeClock::new().set_rate(-1.0);
+    }
+
+    #[test]
+    fn test_policy_permissive() {
+        let clock = FakeClock::new();
+        clock.set(Duration::from_secs(5));
+        let later = clock.now();
+        clock.set(Duration::from_secs(2));
+        assert_eq!(Duration::from_secs(2), clock.since_origin());
+        assert_eq!(Duration::ZERO, clock.elapsed(later));
+    }
+
+    #[test]
+    fn test_policy_saturating() {
+        let clock = FakeClock::new();
+        clock.set_policy(MonotonicPolicy::Saturating);
+        clock.set(Duration::from_secs(5));
+        assert_eq!(Duration::from_secs(5), clock.set(Duration::from_secs(2)));
+        assert_eq!(Duration::from_secs(5), clock.since_origin());
+    }
+
+    #[test]
+    #[should_panic(expected = "fake time set backwards from 5s to 2s")]
+    fn test_policy_strict_set() {
+        let clock = FakeClock::new();
+        clock.set_policy(MonotonicPolicy::Strict);
+        clock.set(Duration::from_secs(5));
+        clock.set(Duration::from_secs(2));
+    }
+
+    #[test]
+    fn test_policy_strict_duration_since() {
+        let clock = FakeClock::new();
+        clock.set_policy(MonotonicPolicy::Strict);
+        let _guard = clock.enter();
+        let earlier = FakeInstant::now();
+        let later = earlier + Duration::from_secs(1);
+        assert_eq!(Duration::from_secs(1), later - earlier);
+        assert_eq!(None, earlier.checked_duration_since(later));
+
+        let result = std::panic::catch_unwind(|| earlier.duration_since(later));
+        assert!(result.is_err());
+        let result = std::panic::catch_unwind(|| later.elapsed());
+        assert!(result.is_err());
+        let result = std::panic::catch_unwind(|| earlier - later);
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn test_on_backwards() {
+        let clock = FakeClock::new();
+        let seen = Arc::new(Mutex::new(Vec::new()));
+        let id = {
+            let seen = seen.clone();
+            clock.on_backwards(move |old, new| seen.lock().unwrap().push((old, new)))
+        };
+        clock.set(Duration::from_secs(5));
+        clock.set(Duration::from_secs(3));
+        clock.advance(Duration::from_secs(1));
+        assert!(clock.remove_hook(id));
+        assert!(!clock.remove_hook(id));
+        clock.set(Duration::ZERO);
+        assert_eq!(
+            vec![(Duration::from_secs(5), Duration::from_secs(3))],
+            *seen.lock().unwrap()
+        );
+    }
+
+    #[test]
+    fn test_jump_hooks() {
+        let clock = FakeClock::new();
+        let _guard = clock.enter();
+        let seen = Arc::new(Mutex::new(Vec::new()));
+        {
+            let seen = seen.clone();
+            clock.on_advance(move |old, new| seen.lock().unwrap().push((old, new)));
+        }
+        crate::executor::block_on(crate::future::sleep(Duration::from_secs(5)));
+        clock.restore(&ClockSnapshot {
+            deadlines: vec![Duration::from_secs(7)],
+            ..clock.snapshot()
+        });
+        clock.advance_to_next();
+        clock.set_auto_advance(true);
+        let _participant = clock.participate();
+        clock.sleep(Duration::from_secs(1));
+        assert_eq!(
+            vec![
+                (Duration::ZERO, Duration::from_secs(5)),
+                (Duration::from_secs(5), Duration::from_secs(7)),
+                (Duration::from_secs(7), Duration::from_secs(8)),
+            ],
+            *seen.lock().unwrap()
+        );
+    }
+
+    #[test]
+    fn test_strict_restore() {
+        let clock = FakeClock::new();
+        clock.set_policy(MonotonicPolicy::Strict);
+        clock.set(Duration::from_secs(10));
+        let seen = Arc::new(Mutex::new(Vec::new()));
+        {
+            let seen = seen.clone();
+            clock.on_backwards(move |old, new| seen.lock().unwrap().push(("backwards", old, new)));
+        }
+        {
+            let seen = seen.clone();
+            clock.on_set(move |old, new| seen.lock().unwrap().push(("set", old, new)));
+        }
+
+        let snapshot = clock.snapshot();
+        {
+            let _guard = clock.guard();
+            clock.advance(Duration::from_secs(5));
+        }
+        assert_eq!(Duration::from_secs(10), clock.since_origin());
+        clock.advance(Duration::from_secs(5));
+        clock.restore(&snapshot);
+        assert_eq!(Duration::from_secs(10), clock.since_origin());
+
+        let backwards = (Duration::from_secs(15), Duration::from_secs(10));
+        assert_eq!(
+            vec![
+                ("backwards", backwards.0, backwards.1),
+                ("set", backwards.0, backwards.1),
+                ("backwards", backwards.0, backwards.1),
+                ("set", backwards.0, backwards.1),
+            ],
+            *seen.lock().unwrap()
+        );
+    }
+
+    #[test]
+    fn test_on_set_and_advance() {
+        let clock = FakeClock::new();
+        clock.set_policy(MonotonicPolicy::Saturating);
+        let seen = Arc::new(Mutex::new(Vec::new()));
+        let set_id = {
+            let seen = seen.clone();
+            clock.on_set(move |old, new| seen.lock().unwrap().push(("set", old, new)))
+        };
+        let advance_id = {
+            let seen = seen.clone();
+            clock.on_advance(move |old, new| seen.lock().unwrap().push(("advance", old, new)))
+        };
+        clock.set(Duration::from_secs(5));
+        clock.advance(Duration::from_secs(2));
+        clock.set(Duration::from_secs(1));
+        assert!(clock.remove_hook(set_id));
+        assert!(clock.remove_hook(advance_id));
+        clock.set(Duration::from_secs(10));
+        clock.advance(Duration::from_secs(1));
+        assert_eq!(
+            vec![
+                ("set", Duration::ZERO, Duration::from_secs(5)),
+                ("advance", Duration::from_secs(5), Duration::from_secs(7)),
+            ],
+            *seen.lock().unwrap()
+        );
+    }
+
+    #[test]
+    fn test_trace() {
+        let clock = FakeClock::new();
+        clock.set(Duration::from_secs(1));
+        let guard = clock.trace(false);
+        let set_line = line!() + 1;
+        clock.set(Duration::from_secs(5));
+        clock.advance(Duration::from_secs(2));
+        clock.now();
+        let timeline = guard.timeline();
+        assert_eq!(
+            vec![
+                (TraceKind::Set, Duration::from_secs(5)),
+                (TraceKind::Advance, Duration::from_secs(7)),
+            ],
+            timeline
+                .iter()
+                .map(|event| (event.kind, event.time))
+                .collect::<Vec<_>>()
+        );
+        assert_eq!(file!(), timeline[0].location.file());
+        assert_eq!(set_line, timeline[0].location.line());
+        assert_eq!(set_line + 1, timeline[1].location.line());
+        assert_eq!(thread::current().id(), timeline[0].thread);
+        drop(guard);
+        assert!(clock.timeline().is_empty());
+
+        let _guard = clock.trace(true);
+        clock.since_origin();
+        assert_eq!(
+            vec![TraceKind::Read],
+            clock
+                .timeline()
+                .iter()
+                .map(|event| event.kind)
+                .collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn test_trace_sources() {
+        let clock = FakeClock::new();
+        let _enter = clock.enter();
+        let _trace = clock.trace(true);
+        let line = line!();
+        FakeInstant::ORIGIN.elapsed();
+        clock.system_now();
+        FakeSystemTime::now();
+        clock.elapsed(FakeInstant::ORIGIN);
+        clock.set_policy(MonotonicPolicy::Saturating);
+        clock.set(Duration::from_secs(1));
+        clock.set(Duration::ZERO);
+        clock.restore(&ClockSnapshot {
+            now: Duration::from_secs(2),
+            deadlines: vec![Duration::from_secs(3)],
+            ..ClockSnapshot::default()
+        });
+        clock.advance_to_next();
+        clock.set_auto_advance(true);
+        let _participant = clock.participate();
+        clock.sleep(Duration::from_secs(1));
+        clock.pass_through(Duration::from_secs(3600));
+
+        let timeline: Vec<_> = clock
+            .timeline()
+            .iter()
+            .map(|event| {
+                assert_eq!(file!(), event.location.file());
+                (event.kind, event.location.line() - line)
+            })
+            .collect();
+        assert_eq!(
+            vec![
+                (TraceKind::Read, 1),
+                (TraceKind::Read, 2),
+                (TraceKind::Read, 3),
+                (TraceKind::Read, 4),
+                (TraceKind::Set, 6),
+                (TraceKind::Restore, 8),
+                (TraceKind::Jump, 13),
+                (TraceKind::Read, 16),
+                (TraceKind::Jump, 16),
+                (TraceKind::Set, 17),
+            ],
+            timeline
+        );
+    }
+
+    #[test]
+    fn test_scoped_time() {
+        let _clock = FakeClock::new().enter();
+        FakeInstant::set_time(10);
+        {
+            let _guard = FakeInstant::scoped_time(500);
+            assert_eq!(500, FakeInstant::time());
+            FakeInstant::advance_time(5);
+        }
+        assert_eq!(10, FakeInstant::time());
+    }
+
+    #[test]
+    fn test_enter() {
+        let _clock = FakeClock::new().enter();
+        let clock = FakeClock::new();
+        clock.set(Duration::from_secs(5));
+        FakeInstant::set(Duration::from_secs(1));
+        {
+            let _guard = clock.enter();
+            assert_eq!(5000, FakeInstant::time());
+            FakeInstant::advance_time(10);
+            {
+                let _inner = FakeClock::new().enter();
+                assert_eq!(0, FakeInstant::time());
+            }
+            assert_eq!(5010, FakeInstant::time());
+        }
+        assert_eq!(1000, FakeInstant::time());
+        assert_eq!(Duration::from_millis(5010), clock.since_origin());
+    }
+}
@@ -0,0 +1,367 @@
+// Copyright 2018 MaidSafe.net limited.
+//
+// This SAFE Network Software is licensed to you under the MIT license <LICENSE-MIT
+// http://opensource.org/licenses/MIT> or the Modified BSD license <LICENSE-BSD
+// https://opensource.org/licenses/BSD-3-Clause>, at your option. This file may not be copied,
+// modified, or distributed except according to those terms. Please review the Licences for the
+// specific language governing permissions and limitations relating to use of the SAFE Network
+// Software.
+
+//! Executors driving futures with the fake clock as their only time source.
+
+use crate::FakeClock;
+use std::cell::RefCell;
+use std::collections::{BTreeMap, VecDeque};
+use std::future::Future;
+use std::pin::{pin, Pin};
+use std::rc::Rc;
+use std::sync::{Arc, Mutex, MutexGuard, PoisonError};
+use std::task::{Context, Poll, Wake, Waker};
+use std::thread::{self, Thread};
+
+/// A spawned task, polled on the thread running its executor.
+type Task = Pin<Box<dyn Future<Output = ()>>>;
+
+/// The task id of the future passed to `block_on`.
+const MAIN: usize = 0;
+
+thread_local! {
+    static SPAWNED: RefCell<Option<Vec<Task>>> = const { RefCell::new(None) };
+}
+
+/// Runs `future` to completion on the calling thread, along with any tasks
+/// [spawned](spawn) while it runs.
+///
+/// Ready tasks are polled in the order they were woken. Whenever no task is
+/// ready, the current clock is advanced to its next pending deadline, so fake
+/// sleeps and timeouts complete without waiting in real time. If there is no
+/// pending deadline either, the thread parks until a task is woken from
+/// elsewhere.
+///
+/// Spawned tasks that have not completed when `future` does are dropped.
+#[track_caller]
+pub fn block_on<F: Future>(future: F) -> F::Output {
+    let clock = FakeClock::current();
+    let mut future = pin!(future);
+    let signal = Arc::new(Signal {
+        thread: thread::current(),
+        ready: Mutex::new(VecDeque::from([MAIN])),
+    });
+    let main_waker = signal.waker(MAIN);
+    let mut tasks = BTreeMap::<usize, Task>::new();
+    let mut next_id = MAIN + 1;
+    let _spawner = Spawner::enter();
+    loop {
+        while let Some(id) = signal.pop() {
+            if id == MAIN {
+                let mut cx = Context::from_waker(&main_waker);
+                if let Poll::Ready(output) = future.as_mut().poll(&mut cx) {
+                    return output;
+                }
+            } else if let Some(task) = tasks.get_mut(&id) {
+                let waker = signal.waker(id);
+                if task
+                    .as_mut()
+                    .poll(&mut Context::from_waker(&waker))
+                    .is_ready()
+                {
+                    tasks.remove(&id);
+                }
+            }
+            for task in Spawner::take() {
+                tasks.insert(next_id, task);
+                signal.push(next_id);
+                next_id += 1;
+            }
+        }
+        if clock.advance_to_next().is_none() {
+            signal.wait();
+        }
+    }
+}
+
+/// Spawns `future` as a new task on the executor running on this thread,
+/// returning a handle that resolves to its output.
+///
+/// The task runs until it completes or the enclosing [`block_on`] returns,
+/// even if the handle is dropped.
+///
+/// # Panics
+///
+/// Panics if called outside of `block_on`.
+pub fn spawn<F>(future: F) -> JoinHandle<F::Output>
+where
+    F: Future + 'static,
+    F::Output: 'static,
+{
+    let state = Rc::new(RefCell::new(JoinState {
+        output: None,
+        finished: false,
+        waker: None,
+    }));
+    let task_state = Rc::clone(&state);
+    let task = Box::pin(async move {
+        let output = future.await;
+        let mut state = task_state.borrow_mut();
+        state.output = Some(output);
+        state.finished = true;
+        if let Some(waker) = state.waker.take() {
+            waker.wake();
+        }
+    });
+    SPAWNED.with(|spawned| {
+        spawned
+            .borrow_mut()
+            .as_mut()
+            .expect("`spawn` called outside of `block_on`")
+            .push(task)
+    });
+    JoinHandle { state }
+}
+
+/// A handle to a [spawned](spawn) task, resolving to the task's output.
+#[derive(Debug)]
+pub struct JoinHandle<T> {
+    state: Rc<RefCell<JoinState<T>>>,
+}
+
+#[derive(Debug)]
+struct JoinState<T> {
+    output: Option<T>,
+    finished: bool,
+    waker: Option<Waker>,
+}
+
+impl<T> JoinHandle<T> {
+    /// Returns `true` if the task has completed, including after its output
+    /// has been taken by awaiting the handle.
+    pub fn is_finished(&self) -> bool {
+        self.state.borrow().finished
+    }
+}
+
+impl<T> Future for JoinHandle<T> {
+    type Output = T;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<T> {
+        let mut state = self.state.borrow_mut();
+        match state.output.take() {
+            Some(output) => Poll::Ready(output),
+            None => {
+                state.waker = Some(cx.waker().clone());
+                Poll::Pending
+            }
+        }
+    }
+}
+
+/// Collects the tasks spawned on this thread while an executor runs,
+/// restoring those of any enclosing executor when dropped.
+struct Spawner {
+    outer: Option<Vec<Task>>,
+}
+
+impl Spawner {
+    fn enter() -> Self {
+        let outer = SPAWNED.with(|spawned| spawned.replace(Some(Vec::new())));
+        Self { outer }
+    }
+
+    /// Takes the tasks spawned since the last call.
+    fn take() -> Vec<Task> {
+        SPAWNED.with(|spawned| {
+            spawned
+                .borrow_mut()
+                .as_mut()
+                .map(std::mem::take)
+                .unwrap_or_default()
+        })
+    }
+}
+
+impl Drop for Spawner {
+    fn drop(&mut self) {
+        let outer = self.outer.take();
+        SPAWNED.with(|spawned| *spawned.borrow_mut() = outer);
+    }
+}
+
+/// Wakes a thread blocked in an executor, queueing the woken tasks.
+struct Signal {
+    thread: Thread,
+    ready: Mutex<VecDeque<usize>>,
+}
+
+impl Signal {
+    fn waker(self: &Arc<Self>, id: usize) -> Waker {
+        Waker::from(Arc::new(TaskWaker {
+            id,
+            signal: Arc::clone(self),
+        }))
+    }
+
+    fn queue(&self) -> MutexGuard<'_, VecDeque<usize>> {
+        self.ready.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    /// Queues task `id` to be polled, unless it is already queued.
+    fn push(&self, id: usize) {
+        let mut queue = self.queue();
+        if !queue.contains(&id) {
+            queue.push_back(id);
+        }
+    }
+
+    /// Removes and returns the next task to poll.
+    fn pop(&self) -> Option<usize> {
+        self.queue().pop_front()
+    }
+
+    /// Parks the calling thread until a task is woken.
+    fn wait(&self) {
+        while self.queue().is_empty() {
+            thread::park();
+        }
+    }
+}
+
+/// Wakes one task of an executor.
+struct TaskWaker {
+    id: usize,
+    signal: Arc<Signal>,
+}
+
+impl Wake for TaskWaker {
+    fn wake(self: Arc<Self>) {
+        self.wake_by_ref();
+    }
+
+    fn wake_by_ref(self: &Arc<Self>) {
+        self.signal.push(self.id);
+        self.signal.thread.unpark();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::future::{interval, sleep, timeout};
+    use crate::FakeInstant;
+    use std::time::Duration;
+
+    #[test]
+    fn test_block_on_sleep() {
+        let _clock = FakeClock::new().enter();
+        let start = FakeInstant::now();
+        block_on(sleep(Duration::from_secs(3600)));
+        assert_eq!(Duration::from_secs(3600), start.elapsed());
+    }
+
+    #[test]
+    fn test_block_on_timeout() {
+        let _clock = FakeClock::new().enter();
+        let result = block_on(timeout(
+            Duration::from_secs(1),
+            sleep(Duration::from_secs(2)),
+        ));
+        assert!(result.is_err());
+        assert_eq!(1000, FakeInstant::time());
+    }
+
+    #[test]
+    fn test_block_on_interval() {
+        let _clock = FakeClock::new().enter();
+        let ticks = block_on(async {
+            let mut interval = interval(Duration::from_millis(10));
+            let mut ticks = Vec::new();
+            for _ in 0..3 {
+                ticks.push(interval.tick().await);
+            }
+            ticks
+        });
+        assert_eq!(
+            vec![
+                FakeInstant::ORIGIN,
+                FakeInstant::ORIGIN + Duration::from_millis(10),
+                FakeInstant::ORIGIN + Duration::from_millis(20),
+            ],
+            ticks
+        );
+    }
+
+    #[test]
+    fn test_block_on_woken_by_thread() {
+        let _clock = FakeClock::new().enter();
+        let (tx, rx) = std::sync::mpsc::channel::<Waker>();
+        let waker_thread = thread::spawn(move || rx.recv().unwrap().wake());
+        let mut polled = false;
+        block_on(std::future::poll_fn(|cx| {
+            if polled {
+                return Poll::Ready(());
+            }
+            polled = true;
+            tx.send(cx.waker().clone()).unwrap();
+            Poll::Pending
+        }));
+        waker_thread.join().unwrap();
+        assert_eq!(0, FakeInstant::time());
+    }
+
+    #[test]
+    fn test_spawn() {
+        let _clock = FakeClock::new().enter();
+        let order = Rc::new(RefCell::new(Vec::new()));
+        let total = block_on(async {
+            let handles: Vec<_> = [30, 10, 20]
+                .into_iter()
+                .map(|millis| {
+                    let order = Rc::clone(&order);
+                    spawn(async move {
+                        sleep(Duration::from_millis(millis)).await;
+                        order.borrow_mut().push(millis);
+                        millis
+                    })
+                })
+                .collect();
+            let mut total = 0;
+            for handle in handles {
+                total += handle.await;
+            }
+            total
+        });
+        assert_eq!(60, total);
+        assert_eq!(vec![10, 20, 30], *order.borrow());
+        assert_eq!(30, FakeInstant::time());
+    }
+
+    #[test]
+    fn test_spawn_detached() {
+        let _clock = FakeClock::new().enter();
+        let finished = Rc::new(RefCell::new(Vec::new()));
+        block_on(async {
+            for millis in [10, 1000] {
+                let finished = Rc::clone(&finished);
+                drop(spawn(async move {
+                    sleep(Duration::from_millis(millis)).await;
+                    finished.borrow_mut().push(millis);
+                }));
+            }
+            let handle = spawn(async {});
+            sleep(Duration::from_millis(100)).await;
+            assert!(handle.is_finished());
+            let mut handle = spawn(sleep(Duration::from_millis(10)));
+            assert!(!handle.is_finished());
+            (&mut handle).await;
+            assert!(handle.is_finished());
+        });
+        // The task still sleeping when `block_on` returned was dropped.
+        assert_eq!(vec![10], *finished.borrow());
+        assert_eq!(110, FakeInstant::time());
+    }
+
+    #[test]
+    #[should_panic(expected = "outside of `block_on`")]
+    fn test_spawn_outside_block_on() {
+        drop(spawn(async {}));
+    }
+}
@@ -0,0 +1,398 @@
+// Copyright 2018 MaidSafe.net limited.
+//
+// This SAFE Network Software is licensed to you under the MIT license <LICENSE-MIT
+// http://opensource.org/licenses/MIT> or the Modified BSD license <LICENSE-BSD
+// https://opensource.org/licenses/BSD-3-Clause>, at your option. This file may not be copied,
+// modified, or distributed except according to those terms. Please review the Licences for the
+// specific language governing permissions and limitations relating to use of the SAFE Network
+// Software.
+
+//! Futures driven by the fake clock, mimicking `tokio::time`.
+//!
+//! These futures do not depend on any particular runtime. Each one registers
+//! its waker with the clock that was current when it was created, and is
+//! woken as soon as that clock is set or advanced past its deadline.
+
+use crate::clock::{FakeClock, TimerKey};
+use crate::FakeInstant;
+use std::error::Error;
+use std::fmt;
+use std::future::Future;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+use std::time::Duration;
+
+/// Returns a future that completes once the current fake clock has advanced
+/// by `duration`.
+pub fn sleep(duration: Duration) -> Sleep {
+    let clock = FakeClock::current();
+    let deadline = clock.now() + duration;
+    Sleep::new(clock, deadline)
+}
+
+/// Returns a future that completes once the current fake clock reaches
+/// `deadline`.
+pub fn sleep_until(deadline: FakeInstant) -> Sleep {
+    Sleep::new(FakeClock::current(), deadline)
+}
+
+/// Requires `future` to complete before the current fake clock has advanced
+/// by `duration`.
+pub fn timeout<F: Future>(duration: Duration, future: F) -> Timeout<F> {
+    Timeout {
+        future: Box::pin(future),
+        sleep: sleep(duration),
+    }
+}
+
+/// Requires `future` to complete before the current fake clock reaches
+/// `deadline`.
+pub fn timeout_at<F: Future>(deadline: FakeInstant, future: F) -> Timeout<F> {
+    Timeout {
+        future: Box::pin(future),
+        sleep: sleep_until(deadline),
+    }
+}
+
+/// Returns an `Interval` whose first tick completes immediately and which then
+/// ticks every `period` of fake time.
+///
+/// # Panics
+///
+/// Panics if `period` is zero.
+pub fn interval(period: Duration) -> Interval {
+    interval_at(FakeInstant::now(), period)
+}
+
+/// Returns an `Interval` whose first tick completes at `start` and which then
+/// ticks every `period` of fake time.
+///
+/// # Panics
+///
+/// Panics if `period` is zero.
+pub fn interval_at(start: FakeInstant, period: Duration) -> Interval {
+    assert!(!period.is_zero(), "`period` must be non-zero");
+    Interval {
+        sleep: sleep_until(start),
+        period,
+    }
+}
+
+/// Wraps `future` so that `clock` is the current clock whenever it is
+/// polled, whichever thread polls it.
+///
+/// Async tasks may migrate between the worker threads of an executor, so a
+/// task reading the thread's current clock could otherwise see different
+/// clocks from one poll to the next. Wrap each task when spawning it to give
+/// it a task-local clock.
+pub fn with_clock<F: Future>(clock: FakeClock, future: F) -> WithClock<F> {
+    WithClock {
+        future: Box::pin(future),
+        clock,
+    }
+}
+
+/// Wraps `future` so that the calling thread's current clock is also current
+/// whenever it is polled. See [`with_clock`].
+pub fn with_current_clock<F: Future>(future: F) -> WithClock<F> {
+    with_clock(FakeClock::current(), future)
+}
+
+/// Future returned by [`with_clock`] and [`with_current_clock`].
+#[must_use = "futures do nothing unless polled"]
+pub struct WithClock<F> {
+    future: Pin<Box<F>>,
+    clock: FakeClock,
+}
+
+impl<F> WithClock<F> {
+    /// Returns the clock the wrapped future runs with.
+    pub fn clock(&self) -> &FakeClock {
+        &self.clock
+    }
+}
+
+impl<F: Future> Future for WithClock<F> {
+    type Output = F::Output;
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<F::Output> {
+        let this = self.get_mut();
+        let _guard = this.clock.enter();
+        this.future.as_mut().poll(cx)
+    }
+}
+
+impl<F> fmt::Debug for WithClock<F> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("WithClock")
+            .field("clock", &self.clock)
+            .finish_non_exhaustive()
+    }
+}
+
+/// Future returned by [`sleep`] and [`sleep_until`].
+#[must_use = "futures do nothing unless polled"]
+#[derive(Debug)]
+pub struct Sleep {
+    clock: FakeClock,
+    deadline: FakeInstant,
+    timer: Option<TimerKey>,
+}
+
+impl Sleep {
+    fn new(clock: FakeClock, deadline: FakeInstant) -> Self {
+        Self {
+            clock,
+            deadline,
+            timer: None,
+        }
+    }
+
+    /// Returns the instant at which this future completes.
+    pub fn deadline(&self) -> FakeInstant {
+        self.deadline
+    }
+
+    /// Returns `true` if the deadline has been reached.
+    pub fn is_elapsed(&self) -> bool {
+        self.clock.now() >= self.deadline
+    }
+
+    /// Changes the deadline, re-arming the future if it already completed.
+    pub fn reset(&mut self, deadline: FakeInstant) {
+        self.clock.cancel_timer(&mut self.timer);
+        self.deadline = deadline;
+    }
+}
+
+impl Future for Sleep {
+    type Output = ();
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<()> {
+        let this = self.get_mut();
+        this.clock
+            .poll_timer(this.deadline.time_created, &mut this.timer, cx.waker())
+    }
+}
+
+impl Drop for Sleep {
+    fn drop(&mut self) {
+        self.clock.cancel_timer(&mut self.timer);
+    }
+}
+
+/// Future returned by [`timeout`] and [`timeout_at`].
+#[must_use = "futures do nothing unless polled"]
+pub struct Timeout<F> {
+    future: Pin<Box<F>>,
+    sleep: Sleep,
+}
+
+impl<F> Timeout<F> {
+    /// Returns a reference to the wrapped future.
+    pub fn get_ref(&self) -> &F {
+        &self.future
+    }
+
+    /// Consumes the `Timeout`, returning the wrapped future.
+    pub fn into_inner(self) -> Pin<Box<F>> {
+        self.future
+    }
+}
+
+impl<F: Future> Future for Timeout<F> {
+    type Output = Result<F::Output, Elapsed>;
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let this = self.get_mut();
+        if let Poll::Ready(output) = this.future.as_mut().poll(cx) {
+            return Poll::Ready(Ok(output));
+        }
+        Pin::new(&mut this.sleep)
+            .poll(cx)
+            .map(|()| Err(Elapsed(())))
+    }
+}
+
+impl<F> fmt::Debug for Timeout<F> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Timeout")
+            .field("sleep", &self.sleep)
+            .finish_non_exhaustive()
+    }
+}
+
+/// Error returned by [`Timeout`] when the deadline is reached before the
+/// wrapped future completes.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Elapsed(());
+
+impl fmt::Display for Elapsed {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "deadline has elapsed")
+    }
+}
+
+impl Error for Elapsed {}
+
+/// Stream of evenly spaced ticks, returned by [`interval`] and
+/// [`interval_at`].
+///
+/// Ticks missed because the clock jumped over several periods at once are
+/// delivered back-to-back, each reporting its scheduled instant.
+#[derive(Debug)]
+pub struct Interval {
+    sleep: Sleep,
+    period: Duration,
+}
+
+impl Interval {
+    /// Completes when the next tick is reached, returning its scheduled
+    /// instant.
+    pub async fn tick(&mut self) -> FakeInstant {
+        std::future::poll_fn(|cx| self.poll_tick(cx)).await
+    }
+
+    /// Polls for the next tick, returning its scheduled instant once reached.
+    pub fn poll_tick(&mut self, cx: &mut Context<'_>) -> Poll<FakeInstant> {
+        let scheduled = self.sleep.deadline();
+        Pin::new(&mut self.sleep).poll(cx).map(|()| {
+            self.sleep.reset(scheduled + self.period);
+            scheduled
+        })
+    }
+
+    /// Returns the period between ticks.
+    pub fn period(&self) -> Duration {
+        self.period
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+    use std::task::{Wake, Waker};
+
+    #[derive(Default)]
+    struct CountingWaker(AtomicUsize);
+
+    impl Wake for CountingWaker {
+        fn wake(self: Arc<Self>) {
+            self.0.fetch_add(1, Ordering::SeqCst);
+        }
+    }
+
+    fn poll<F: Future + Unpin>(future: &mut F, waker: &Arc<CountingWaker>) -> Poll<F::Output> {
+        let waker = Waker::from(waker.clone());
+        Pin::new(future).poll(&mut Context::from_waker(&waker))
+    }
+
+    #[test]
+    fn test_sleep_woken_on_advance() {
+        let _clock = FakeClock::new().enter();
+        let waker = Arc::new(CountingWaker::default());
+        let mut sleep = sleep(Duration::from_millis(100));
+        assert_eq!(Poll::Pending, poll(&mut sleep, &waker));
+
+        FakeInstant::advance_time(99);
+        assert_eq!(0, waker.0.load(Ordering::SeqCst));
+        FakeInstant::advance_time(1);
+        assert_eq!(1, waker.0.load(Ordering::SeqCst));
+        assert_eq!(Poll::Ready(()), poll(&mut sleep, &waker));
+    }
+
+    #[test]
+    fn test_sleep_drop_unregisters() {
+        let _clock = FakeClock::new().enter();
+        let waker = Arc::new(CountingWaker::default());
+        let mut sleep = sleep(Duration::from_millis(100));
+        assert_eq!(Poll::Pending, poll(&mut sleep, &waker));
+        drop(sleep);
+        FakeInstant::advance_time(100);
+        assert_eq!(0, waker.0.load(Ordering::SeqCst));
+    }
+
+    #[test]
+    fn test_timeout() {
+        let _clock = FakeClock::new().enter();
+        let waker = Arc::new(CountingWaker::default());
+        let mut ok = timeout(Duration::from_millis(50), async { 5 });
+        assert_eq!(Poll::Ready(Ok(5)), poll(&mut ok, &waker));
+
+        let mut elapsed = timeout(Duration::from_millis(50), std::future::pending::<()>());
+        assert_eq!(Poll::Pending, poll(&mut elapsed, &waker));
+        FakeInstant::advance_time(50);
+        assert_eq!(1, waker.0.load(Ordering::SeqCst));
+        assert_eq!(Poll::Ready(Err(Elapsed(()))), poll(&mut elapsed, &waker));
+    }
+
+    #[test]
+    fn test_interval() {
+        let _clock = FakeClock::new().enter();
+        let waker = Arc::new(CountingWaker::default());
+        let start = FakeInstant::now();
+        let mut interval = interval(Duration::from_millis(10));
+        let tick = |interval: &mut Interval| {
+            let waker = Waker::from(waker.clone());
+            interval.poll_tick(&mut Context::from_waker(&waker))
+        };
+
+        assert_eq!(Poll::Ready(start), tick(&mut interval));
+        assert_eq!(Poll::Pending, tick(&mut interval));
+        FakeInstant::advance_time(25);
+        assert_eq!(
+            Poll::Ready(start + Duration::from_millis(10)),
+            tick(&mut interval)
+        );
+        assert_eq!(
+            Poll::Ready(start + Duration::from_millis(20)),
+            tick(&mut interval)
+        );
+        assert_eq!(Poll::Pending, tick(&mut interval));
+    }
+
+    #[test]
+    fn test_with_clock() {
+        let _clock = FakeClock::new().enter();
+        let waker = Arc::new(CountingWaker::default());
+        let clock = FakeClock::new();
+        clock.set(Duration::from_secs(100));
+        let mut task = with_clock(
+            clock.clone(),
+            std::future::poll_fn(|_| {
+                let now = FakeInstant::now();
+                if now.duration_since(FakeInstant::ORIGIN) >= Duration::from_secs(101) {
+                    Poll::Ready(now)
+                } else {
+                    Poll::Pending
+                }
+            }),
+        );
+        assert_eq!(Poll::Pending, poll(&mut task, &waker));
+
+        clock.advance(Duration::from_secs(1));
+        let waker_clone = waker.clone();
+        let polled = std::thread::spawn(move || {
+            let _other = FakeClock::new().enter();
+            poll(&mut task, &waker_clone)
+        })
+        .join()
+        .unwrap();
+        assert_eq!(Poll::Ready(clock.now()), polled);
+        assert_eq!(0, FakeInstant::time());
+    }
+
+    #[test]
+    fn test_woken_from_other_thread() {
+        let clock = FakeClock::new();
+        let _guard = clock.enter();
+        let waker = Arc::new(CountingWaker::default());
+        let mut sleep = sleep(Duration::from_secs(1));
+        assert_eq!(Poll::Pending, poll(&mut sleep, &waker));
+
+        std::thread::spawn(move || clock.advance(Duration::from_secs(1)))
+            .join()
+            .unwrap();
+        assert_eq!(1, waker.0.load(Ordering::SeqCst));
+        assert_eq!(Poll::Ready(()), poll(&mut sleep, &waker));
+    }
+}
@@ -14,13 +14,15 @@
 
 // For explanation of lint checks, run `rustc -W help`.
 #![forbid(
-    bad_style,
     arithmetic_overflow,
     mutable_transmutes,
     no_mangle_const_items,
     unknown_crate_types
 )]
+// `bad_style` is denied rather than forbidden, since serde's derives allow
+// lints from that group in the code they generate.
 #![deny(
+    bad_style,
     missing_docs,
     overflowing_literals,
     unsafe_code,
@@ -33,65 +35,217 @@
     unused_must_use
 )]
 
-use std::cell::Cell;
 use std::convert::TryInto;
 use std::ops::{Add, AddAssign, Sub, SubAssign};
-use std::time::Duration;
+use std::panic::Location;
+use std::time::{Duration, Instant};
+
+mod clock;
+pub mod executor;
+pub mod future;
+pub mod sim;
+pub mod sync;
+mod system_time;
+pub mod thread;
+pub mod time;
+#[cfg(feature = "tokio")]
+pub mod tokio;
+mod traits;
+
+pub use clock::{
+    ClockMode, ClockSnapshot, EnterGuard, FakeClock, HookId, MonotonicPolicy, Participant,
+    TimeGuard, TraceEvent, TraceGuard, TraceKind,
+};
+pub use system_time::{FakeSystemTime, FakeSystemTimeError};
+pub use traits::{Clock, ClockInstant, RealClock};
+
+#[cfg(feature = "macros")]
+pub use fake_instant_macros::test;
+
+#[doc(hidden)]
+pub mod __private {
+    pub use crate::clock::TestClock;
+}
+
+/// Blocks the calling thread until the current fake time has advanced by at
+/// least `duration`, mimicking `std::thread::sleep`.
+///
+/// The clock must be advanced by another thread, so the current clock should
+/// be shared, e.g. through `ClockMode::Global` or a [`FakeClock`] entered on
+/// both threads.
+#[track_caller]
+pub fn sleep(duration: Duration) {
+    let location = Location::caller();
+    clock::with_current(|clock| {
+        let deadline = clock.traced_since_origin(location).checked_add(duration);
+        let deadline = deadline.expect("overflow when computing sleep deadline");
+        clock.wait_until(deadline, location)
+    })
+}
 
-thread_local! {
-    static FAKE_TIME: Cell<u64> = Default::default();
+/// Blocks the calling thread until the current fake time reaches `deadline`.
+///
+/// See [`sleep`] for how the clock is expected to be advanced.
+#[track_caller]
+pub fn sleep_until(deadline: FakeInstant) {
+    let location = Location::caller();
+    clock::with_current(|clock| clock.wait_until(deadline.time_created, location))
 }
 
 /// Struct representing a fake instant.
+///
+/// A `FakeInstant` is fully described by its duration since
+/// [`FakeInstant::ORIGIN`], the instant at which every clock starts; use
+/// `instant.duration_since(FakeInstant::ORIGIN)` and `FakeInstant::ORIGIN +
+/// duration` to convert to and from that representation, e.g. for
+/// persistence. With the `serde` feature, a `FakeInstant` is serialized as
+/// exactly that `Duration`.
 #[derive(Clone, Copy, Debug, Hash, PartialEq, Eq, PartialOrd, Ord)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(transparent)
+)]
 pub struct FakeInstant {
-    time_created: u64,
+    time_created: Duration,
 }
 
 impl FakeInstant {
-    /// Sets the thread-local fake time to the given value, returning the old
-    /// fake time.
+    /// The instant corresponding to a fake time of zero.
+    pub const ORIGIN: Self = Self {
+        time_created: Duration::ZERO,
+    };
+
+    /// Switches between thread-local and process-wide fake time, returning the
+    /// previous mode.
+    ///
+    /// The thread-local and global times are tracked independently, so
+    /// switching modes does not carry the current time over.
+    pub fn set_clock_mode(mode: ClockMode) -> ClockMode {
+        clock::set_mode(mode)
+    }
+
+    /// Returns the current `ClockMode`.
+    pub fn clock_mode() -> ClockMode {
+        clock::mode()
+    }
+
+    /// Sets the current fake time to the given value in milliseconds,
+    /// returning the old fake time.
+    ///
+    /// The returned value is truncated to whole milliseconds; use
+    /// [`FakeInstant::set`] to keep full precision.
+    #[track_caller]
     pub fn set_time(time: u64) -> u64 {
-        FAKE_TIME.with(|c| c.replace(time))
+        as_millis(Self::set(Duration::from_millis(time)))
     }
 
-    /// Advances the thread-local fake time by the given amount of
-    /// milliseconds, returns the new fake time.
+    /// Sets the current fake time to the given value in milliseconds until the
+    /// returned guard is dropped, at which point the previous fake time is
+    /// restored.
+    ///
+    /// Starting each test with a scoped time keeps it from leaking into later
+    /// tests run on the same thread.
+    #[track_caller]
+    pub fn scoped_time(time: u64) -> TimeGuard {
+        let location = Location::caller();
+        let guard = clock::with_current(|clock| clock.traced_guard(location));
+        Self::set_time(time);
+        guard
+    }
+
+    /// Advances the current fake time by the given amount of milliseconds,
+    /// returns the new fake time.
+    ///
+    /// The returned value is truncated to whole milliseconds; use
+    /// [`FakeInstant::advance`] to keep full precision.
+    #[track_caller]
     pub fn advance_time(millis: u64) -> u64 {
-        FAKE_TIME.with(|c| {
-            let new_time = c.get() + millis;
-            c.set(new_time);
-            new_time
-        })
+        as_millis(Self::advance(Duration::from_millis(millis)))
     }
 
-    /// Returns the current thread-local fake time.
+    /// Returns the current fake time in milliseconds, truncating any
+    /// sub-millisecond part.
+    #[track_caller]
     pub fn time() -> u64 {
-        FAKE_TIME.with(|c| c.get())
+        as_millis(Self::since_origin())
     }
 
-    /// Returns a `FakeInstant` instance representing the current thread-local
+    /// Sets the current fake time to the given duration since the origin,
+    /// returning the old fake time.
+    #[track_caller]
+    pub fn set(time: Duration) -> Duration {
+        let location = Location::caller();
+        clock::with_current(|clock| clock.traced_set(time, location))
+    }
+
+    /// Advances the current fake time by the given duration, returns the new
     /// fake time.
+    #[track_caller]
+    pub fn advance(duration: Duration) -> Duration {
+        let location = Location::caller();
+        clock::with_current(|clock| clock.traced_advance(duration, location))
+    }
+
+    /// Returns the current fake time as a duration since the origin.
+    #[track_caller]
+    pub fn since_origin() -> Duration {
+        let location = Location::caller();
+        clock::with_current(|clock| clock.traced_since_origin(location))
+    }
+
+    /// Starts tracing changes to the current clock's fake time, and readings
+    /// of it if `reads` is `true`, until the returned guard is dropped.
+    ///
+    /// See [`FakeClock::trace`] for details.
+    pub fn trace(reads: bool) -> TraceGuard {
+        clock::with_current(|clock| clock.trace(reads))
+    }
+
+    /// Returns the earliest deadline of any pending fake sleep or timer future
+    /// on the current clock.
+    pub fn next_deadline() -> Option<Self> {
+        clock::with_current(FakeClock::next_deadline)
+    }
+
+    /// Advances the current fake time to the earliest pending deadline,
+    /// firing every timer due at that time. Returns the new fake time, or
+    /// `None` if there are no pending timers.
+    #[track_caller]
+    pub fn advance_to_next() -> Option<Self> {
+        let location = Location::caller();
+        clock::with_current(|clock| clock.traced_advance_to_next(location))
+    }
+
+    /// Repeatedly advances the current fake time to the earliest pending
+    /// deadline until no timers remain, returning the final fake time.
+    ///
+    /// See [`FakeClock::run_until_idle`] for details.
+    #[track_caller]
+    pub fn run_until_idle() -> Self {
+        let location = Location::caller();
+        clock::with_current(|clock| clock.traced_run_until_idle(location))
+    }
+
+    /// Returns a `FakeInstant` instance representing the current fake time.
+    #[track_caller]
     pub fn now() -> Self {
-        let time = Self::time();
+        let time = Self::since_origin();
         Self { time_created: time }
     }
 
     /// Returns the duration that passed between `self` and `earlier`.
     ///
-    /// Previously this panicked when `earlier` was later than `self`.
-    /// Currently this method returns a `Duration` of zero in that case. Future
-    /// versions may reintroduce the panic in some circumstances.
-    pub fn duration_since(self, earlier: Self) -> Duration {
-        self.checked_duration_since(earlier).unwrap_or_default()
+    /// If `earlier` is later than `self`, this returns a `Duration` of zero or
+    /// panics, depending on the current clock's [`MonotonicPolicy`].
+    pub fn duration_since(&self, earlier: Self) -> Duration {
+        clock::with_current(|clock| clock.duration_between(*self, earlier))
     }
 
     /// Returns the amount of fake time elapsed from another `FakeInstant` to
     /// this one, or `None` if that `FakeInstant` is earlier than this one.
     pub fn checked_duration_since(&self, earlier: Self) -> Option<Duration> {
-        self.time_created
-            .checked_sub(earlier.time_created)
-            .map(Duration::from_millis)
+        self.time_created.checked_sub(earlier.time_created)
     }
 
     /// Returns the amount of fake time elapsed from another `FakeInstant` to
@@ -102,38 +256,94 @@ impl FakeInstant {
     }
 
     /// Returns the duration of time between the creation of `self` until
-    /// the thread-local fake time.
+    /// the current fake time.
     ///
-    /// Sending a `FakeInstant` across threads will result in this being
-    /// computed relative to the destination thread's fake time.
+    /// In `ClockMode::ThreadLocal`, sending a `FakeInstant` across threads will
+    /// result in this being computed relative to the destination thread's fake
+    /// time.
     ///
-    /// Previously this panicked when the current fakse time was earlier than
-    /// `self`. Currently this method returns a `Duration` of zero in that
-    /// case. Future versions may reintroduce the panic in some circumstances.
-    pub fn elapsed(self) -> Duration {
-        Duration::from_millis(Self::time() - self.time_created)
+    /// If the current fake time is earlier than `self`, this returns a
+    /// `Duration` of zero or panics, depending on the current clock's
+    /// [`MonotonicPolicy`].
+    #[track_caller]
+    pub fn elapsed(&self) -> Duration {
+        let location = Location::caller();
+        clock::with_current(|clock| {
+            let now = Self {
+                time_created: clock.traced_since_origin(location),
+            };
+            clock.duration_between(now, *self)
+        })
     }
 
     /// Returns `Some(t)` where `t` is the time `self + duration` if `t` can be
     /// represented as `FakeInstant`, `None` otherwise.
     pub fn checked_add(&self, duration: Duration) -> Option<Self> {
-        duration
-            .as_millis()
-            .checked_add(self.time_created as u128)
-            .and_then(|time| time.try_into().ok())
+        self.time_created
+            .checked_add(duration)
             .map(|time| Self { time_created: time })
     }
 
     /// Returns `Some(t)` where `t` is the time `self - duration` if `t` can be
     /// represented as `FakeInstant`, `None` otherwise.
     pub fn checked_sub(&self, duration: Duration) -> Option<Self> {
-        duration
-            .as_millis()
-            .try_into()
-            .ok()
-            .and_then(|dur| self.time_created.checked_sub(dur))
+        self.time_created
+            .checked_sub(duration)
             .map(|time| Self { time_created: time })
     }
+
+    /// Converts `self` to a real `Instant`, given the real instant `origin`
+    /// that corresponds to [`FakeInstant::ORIGIN`].
+    ///
+    /// This allows fake instants to be handed to APIs that take real ones.
+    /// With the `tokio` feature, `FakeInstant::to_tokio` does the same for
+    /// tokio's instants, and `fake_instant::tokio::lockstep` keeps tokio's
+    /// paused clock in step with the fake clock.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the result cannot be represented as an `Instant`.
+    pub fn to_std(self, origin: Instant) -> Instant {
+        origin
+            .checked_add(self.time_created)
+            .expect("overflow when converting fake instant to instant")
+    }
+
+    /// Converts a real `Instant` to a `FakeInstant`, given the real instant
+    /// `origin` that corresponds to [`FakeInstant::ORIGIN`].
+    ///
+    /// Instants earlier than `origin` saturate to `FakeInstant::ORIGIN`.
+    pub fn from_std(instant: Instant, origin: Instant) -> Self {
+        Self {
+            time_created: instant.saturating_duration_since(origin),
+        }
+    }
+
+    /// Converts `self` to a tokio `Instant`, given the tokio instant `origin`
+    /// that corresponds to [`FakeInstant::ORIGIN`], such as
+    /// [`Lockstep::origin`](crate::tokio::Lockstep::origin).
+    ///
+    /// # Panics
+    ///
+    /// Panics if the result cannot be represented as an `Instant`.
+    #[cfg(feature = "tokio")]
+    pub fn to_tokio(self, origin: ::tokio::time::Instant) -> ::tokio::time::Instant {
+        ::tokio::time::Instant::from_std(self.to_std(origin.into_std()))
+    }
+
+    /// Converts a tokio `Instant` to a `FakeInstant`, given the tokio instant
+    /// `origin` that corresponds to [`FakeInstant::ORIGIN`].
+    ///
+    /// Instants earlier than `origin` saturate to `FakeInstant::ORIGIN`.
+    #[cfg(feature = "tokio")]
+    pub fn from_tokio(instant: ::tokio::time::Instant, origin: ::tokio::time::Instant) -> Self {
+        Self::from_std(instant.into_std(), origin.into_std())
+    }
+}
+
+/// Converts a fake time to whole milliseconds, saturating at `u64::MAX`.
+fn as_millis(time: Duration) -> u64 {
+    time.as_millis().try_into().unwrap_or(u64::MAX)
 }
 
 impl Add<Duration> for FakeInstant {
@@ -173,7 +383,11 @@ impl Sub<Self> for FakeInstant {
 
 #[cfg(test)]
 mod tests {
-    use super::*;
+    // Not a glob import, which would shadow `#[test]` with `fake_instant::test`
+    // when the `macros` feature is enabled. Each test enters its own clock, so
+    // that it is isolated from the others even when the `global` feature makes
+    // global mode the default.
+    use super::{Duration, FakeClock, FakeInstant, Instant};
 
     #[test]
     fn test_auto_traits() {
@@ -186,39 +400,72 @@ mod tests {
 
     #[test]
     fn test_advance_time() {
+        let _clock = FakeClock::new().enter();
         const DUR: u64 = 5300;
         let clock = FakeInstant::now();
         FakeInstant::advance_time(DUR);
         assert_eq!(Duration::from_millis(DUR), clock.elapsed());
     }
 
+    #[test]
+    fn test_advance_sub_millis() {
+        let _clock = FakeClock::new().enter();
+        const DUR: Duration = Duration::from_micros(1500);
+        FakeInstant::set(Duration::ZERO);
+        let inst = FakeInstant::now();
+        assert_eq!(DUR, FakeInstant::advance(DUR));
+        assert_eq!(DUR, inst.elapsed());
+        assert_eq!(1, FakeInstant::time());
+    }
+
+    #[test]
+    fn test_set_returns_old() {
+        let _clock = FakeClock::new().enter();
+        FakeInstant::set(Duration::new(3, 7));
+        assert_eq!(Duration::new(3, 7), FakeInstant::set(Duration::new(2, 9)));
+        assert_eq!(2000, FakeInstant::set_time(5));
+        assert_eq!(Duration::from_millis(5), FakeInstant::since_origin());
+    }
+
+    #[test]
+    fn test_add_sub_round_trip() {
+        let _clock = FakeClock::new().enter();
+        const DUR: Duration = Duration::from_nanos(1_000_000_123);
+        let inst = FakeInstant::now();
+        assert_eq!(inst, inst + DUR - DUR);
+        assert_eq!(DUR, (inst + DUR) - inst);
+    }
+
     #[test]
     fn test_checked_add_some() {
+        let _clock = FakeClock::new().enter();
         FakeInstant::set_time(0);
 
         let inst = FakeInstant::now();
-        let dur = Duration::from_millis(std::u64::MAX);
-        FakeInstant::set_time(std::u64::MAX);
+        let dur = Duration::from_millis(u64::MAX);
+        FakeInstant::set_time(u64::MAX);
 
         assert_eq!(Some(FakeInstant::now()), inst.checked_add(dur));
     }
 
     #[test]
     fn test_checked_add_none() {
+        let _clock = FakeClock::new().enter();
         FakeInstant::set_time(1);
 
         let inst = FakeInstant::now();
-        let dur = Duration::from_millis(std::u64::MAX);
+        let dur = Duration::MAX;
 
         assert_eq!(None, inst.checked_add(dur));
     }
 
     #[test]
     fn test_checked_sub_some() {
-        FakeInstant::set_time(std::u64::MAX);
+        let _clock = FakeClock::new().enter();
+        FakeInstant::set_time(u64::MAX);
 
         let inst = FakeInstant::now();
-        let dur = Duration::from_millis(std::u64::MAX);
+        let dur = Duration::from_millis(u64::MAX);
         FakeInstant::set_time(0);
 
         assert_eq!(Some(FakeInstant::now()), inst.checked_sub(dur));
@@ -226,29 +473,32 @@ mod tests {
 
     #[test]
     fn test_checked_sub_none() {
-        FakeInstant::set_time(std::u64::MAX - 1);
+        let _clock = FakeClock::new().enter();
+        FakeInstant::set_time(u64::MAX - 1);
 
         let inst = FakeInstant::now();
-        let dur = Duration::from_millis(std::u64::MAX);
+        let dur = Duration::from_millis(u64::MAX);
 
         assert_eq!(None, inst.checked_sub(dur));
     }
 
     #[test]
     fn checked_duration_since_some() {
+        let _clock = FakeClock::new().enter();
         FakeInstant::set_time(0);
         let inst0 = FakeInstant::now();
-        FakeInstant::set_time(std::u64::MAX);
+        FakeInstant::set_time(u64::MAX);
         let inst_max = FakeInstant::now();
 
         assert_eq!(
-            Some(Duration::from_millis(std::u64::MAX)),
+            Some(Duration::from_millis(u64::MAX)),
             inst_max.checked_duration_since(inst0)
         );
     }
 
     #[test]
     fn checked_duration_since_none() {
+        let _clock = FakeClock::new().enter();
         FakeInstant::set_time(1);
         let inst1 = FakeInstant::now();
         FakeInstant::set_time(0);
@@ -259,19 +509,21 @@ mod tests {
 
     #[test]
     fn saturating_duration_since_nonzero() {
+        let _clock = FakeClock::new().enter();
         FakeInstant::set_time(0);
         let inst0 = FakeInstant::now();
-        FakeInstant::set_time(std::u64::MAX);
+        FakeInstant::set_time(u64::MAX);
         let inst_max = FakeInstant::now();
 
         assert_eq!(
-            Duration::from_millis(std::u64::MAX),
+            Duration::from_millis(u64::MAX),
             inst_max.saturating_duration_since(inst0)
         );
     }
 
     #[test]
     fn saturating_duration_since_zero() {
+        let _clock = FakeClock::new().enter();
         FakeInstant::set_time(1);
         let inst1 = FakeInstant::now();
         FakeInstant::set_time(0);
@@ -280,14 +532,35 @@ mod tests {
         assert_eq!(Duration::new(0, 0), inst0.saturating_duration_since(inst1));
     }
 
+    #[test]
+    fn test_std_conversion() {
+        let origin = Instant::now();
+        let inst = FakeInstant::ORIGIN + Duration::from_millis(1500);
+        let real = inst.to_std(origin);
+        assert_eq!(Duration::from_millis(1500), real - origin);
+        assert_eq!(inst, FakeInstant::from_std(real, origin));
+        assert_eq!(FakeInstant::ORIGIN, FakeInstant::from_std(origin, real));
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn test_serde() {
+        let inst = FakeInstant::ORIGIN + Duration::new(5, 7);
+        let json = serde_json::to_string(&inst).unwrap();
+        assert_eq!(serde_json::to_string(&Duration::new(5, 7)).unwrap(), json);
+        assert_eq!(inst, serde_json::from_str(&json).unwrap());
+    }
+
     #[test]
     fn test_debug() {
+        let _clock = FakeClock::new().enter();
         let inst = FakeInstant::now();
-        assert_eq!("FakeInstant { time_created: 0 }", format!("{:?}", inst));
+        assert_eq!("FakeInstant { time_created: 0ns }", format!("{:?}", inst));
     }
 
     #[test]
     fn test_threads() {
+        let _clock = FakeClock::new().enter();
         FakeInstant::set_time(200);
         let inst1 = FakeInstant::now();
         assert!(std::thread::spawn(move || {
@@ -0,0 +1,235 @@
+// Copyright 2018 MaidSafe.net limited.
+//
+// This SAFE Network Software is licensed to you under the MIT license <LICENSE-MIT
+// http://opensource.org/licenses/MIT> or the Modified BSD license <LICENSE-BSD
+// https://opensource.org/licenses/BSD-3-Clause>, at your option. This file may not be copied,
+// modified, or distributed except according to those terms. Please review the Licences for the
+// specific language governing permissions and limitations relating to use of the SAFE Network
+// Software.
+
+//! Discrete-event simulation on the fake clock.
+//!
+//! A [`Simulation`] holds events scheduled at fake instants and runs them in
+//! order, setting its clock to each event's time first. Code run by an event
+//! therefore sees that time from `FakeInstant::now`, and can schedule further
+//! events relative to it.
+//!
+//! ```
+//! use fake_instant::sim::Simulation;
+//! use fake_instant::FakeInstant;
+//! use std::time::Duration;
+//!
+//! let mut sim = Simulation::new();
+//! sim.schedule_in(Duration::from_secs(5), |sim| {
+//!     assert_eq!(5000, FakeInstant::time());
+//!     sim.schedule_in(Duration::from_secs(5), |_| {});
+//! });
+//! assert_eq!(2, sim.run());
+//! assert_eq!(10_000, FakeInstant::time());
+//! ```
+
+use crate::{FakeClock, FakeInstant};
+use std::collections::BTreeMap;
+use std::fmt;
+use std::time::Duration;
+
+/// An event callback, given the simulation so it can schedule more events.
+type Event = Box<dyn FnOnce(&mut Simulation)>;
+
+/// A queue of events ordered by fake time, run on a fake clock.
+///
+/// Events scheduled for the same instant run in the order they were
+/// scheduled. Events scheduled for an instant earlier than the clock's
+/// current time run at the current time, after any already due.
+pub struct Simulation {
+    clock: FakeClock,
+    events: BTreeMap<(FakeInstant, u64), Event>,
+    next_seq: u64,
+}
+
+impl Simulation {
+    /// Creates an empty simulation running on the current fake clock.
+    pub fn new() -> Self {
+        Self::with_clock(FakeClock::current())
+    }
+
+    /// Creates an empty simulation running on `clock`.
+    pub fn with_clock(clock: FakeClock) -> Self {
+        Self {
+            clock,
+            events: BTreeMap::new(),
+            next_seq: 0,
+        }
+    }
+
+    /// Returns the clock this simulation runs on.
+    pub fn clock(&self) -> &FakeClock {
+        &self.clock
+    }
+
+    /// Returns the simulation's current fake time.
+    pub fn now(&self) -> FakeInstant {
+        self.clock.now()
+    }
+
+    /// Schedules `event` to run at `at`.
+    pub fn schedule_at(&mut self, at: FakeInstant, event: impl FnOnce(&mut Self) + 'static) {
+        let at = at.max(self.now());
+        self.events.insert((at, self.next_seq), Box::new(event));
+        self.next_seq += 1;
+    }
+
+    /// Schedules `event` to run once `delay` has passed from the current fake
+    /// time.
+    pub fn schedule_in(&mut self, delay: Duration, event: impl FnOnce(&mut Self) + 'static) {
+        let at = self.now() + delay;
+        self.schedule_at(at, event);
+    }
+
+    /// Returns the time of the next scheduled event, if any.
+    pub fn next_event(&self) -> Option<FakeInstant> {
+        self.events.keys().next().map(|&(at, _)| at)
+    }
+
+    /// Returns the number of scheduled events.
+    pub fn len(&self) -> usize {
+        self.events.len()
+    }
+
+    /// Returns `true` if no events are scheduled.
+    pub fn is_empty(&self) -> bool {
+        self.events.is_empty()
+    }
+
+    /// Sets the clock to the time of the next scheduled event and runs it
+    /// with the clock [entered](FakeClock::enter), returning that time, or
+    /// `None` if no events are scheduled.
+    #[track_caller]
+    pub fn step(&mut self) -> Option<FakeInstant> {
+        let ((at, _), event) = self.events.pop_first()?;
+        if at > self.now() {
+            self.clock.set(at.time_created);
+        }
+        let _guard = self.clock.enter();
+        event(self);
+        Some(at)
+    }
+
+    /// Runs every event scheduled at or before `until`, including those
+    /// scheduled by the events themselves, then sets the clock to `until` if
+    /// it is still earlier. Returns the number of events run.
+    #[track_caller]
+    pub fn run_until(&mut self, until: FakeInstant) -> usize {
+        let mut count = 0;
+        while self.next_event().is_some_and(|at| at <= until) {
+            self.step();
+            count += 1;
+        }
+        if until > self.now() {
+            self.clock.set(until.time_created);
+        }
+        count
+    }
+
+    /// Runs events until none are scheduled, returning the number of events
+    /// run.
+    ///
+    /// This never returns if events keep scheduling further events.
+    #[track_caller]
+    pub fn run(&mut self) -> usize {
+        let mut count = 0;
+        while self.step().is_some() {
+            count += 1;
+        }
+        count
+    }
+}
+
+impl Default for Simulation {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl fmt::Debug for Simulation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Simulation")
+            .field("clock", &self.clock)
+            .field("events", &self.events.len())
+            .finish_non_exhaustive()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    #[test]
+    fn test_order() {
+        let _clock = FakeClock::new().enter();
+        let log = Rc::new(RefCell::new(Vec::new()));
+        let mut sim = Simulation::new();
+        for (name, millis) in [("a", 20), ("b", 10), ("c", 20), ("d", 10)] {
+            let log = Rc::clone(&log);
+            sim.schedule_at(
+                FakeInstant::ORIGIN + Duration::from_millis(millis),
+                move |_| log.borrow_mut().push((name, FakeInstant::time())),
+            );
+        }
+        assert_eq!(4, sim.len());
+        assert_eq!(4, sim.run());
+        assert!(sim.is_empty());
+        assert_eq!(
+            vec![("b", 10), ("d", 10), ("a", 20), ("c", 20)],
+            *log.borrow()
+        );
+    }
+
+    #[test]
+    fn test_run_until() {
+        let _clock = FakeClock::new().enter();
+        fn tick(sim: &mut Simulation, log: Rc<RefCell<Vec<u64>>>) {
+            log.borrow_mut().push(FakeInstant::time());
+            sim.schedule_in(Duration::from_secs(1), move |sim| tick(sim, log));
+        }
+
+        let log = Rc::new(RefCell::new(Vec::new()));
+        let mut sim = Simulation::new();
+        let ticks = Rc::clone(&log);
+        sim.schedule_in(Duration::from_secs(1), move |sim| tick(sim, ticks));
+
+        let until = FakeInstant::ORIGIN + Duration::from_millis(3500);
+        assert_eq!(3, sim.run_until(until));
+        assert_eq!(vec![1000, 2000, 3000], *log.borrow());
+        assert_eq!(until, FakeInstant::now());
+        assert_eq!(
+            Some(FakeInstant::ORIGIN + Duration::from_secs(4)),
+            sim.next_event()
+        );
+    }
+
+    #[test]
+    fn test_with_clock() {
+        let _clock = FakeClock::new().enter();
+        let clock = FakeClock::new();
+        let mut sim = Simulation::with_clock(clock.clone());
+        sim.schedule_in(Duration::from_secs(5), |_| {
+            assert_eq!(5000, FakeInstant::time());
+        });
+        assert_eq!(1, sim.run());
+        assert_eq!(Duration::from_secs(5), clock.since_origin());
+        assert_eq!(0, FakeInstant::time());
+    }
+
+    #[test]
+    fn test_schedule_in_past() {
+        let _clock = FakeClock::new().enter();
+        let mut sim = Simulation::new();
+        FakeInstant::set_time(100);
+        sim.schedule_at(FakeInstant::ORIGIN, |_| {});
+        assert_eq!(Some(FakeInstant::now()), sim.step());
+        assert_eq!(100, FakeInstant::time());
+    }
+}
@@ -0,0 +1,290 @@
+// Copyright 2018 MaidSafe.net limited.
+//
+// This SAFE Network Software is licensed to you under the MIT license <LICENSE-MIT
+// http://opensource.org/licenses/MIT> or the Modified BSD license <LICENSE-BSD
+// https://opensource.org/licenses/BSD-3-Clause>, at your option. This file may not be copied,
+// modified, or distributed except according to those terms. Please review the Licences for the
+// specific language governing permissions and limitations relating to use of the SAFE Network
+// Software.
+
+//! Timed waits on channels, condition variables and thread parking whose
+//! timeouts expire on the fake clock.
+//!
+//! Each function here waits for a real event, such as a message or a
+//! notification, like its `std` counterpart, but gives up once the current
+//! fake clock has advanced by the timeout instead of after a real duration.
+//! The fake clock is checked at least every [`POLL_INTERVAL`] of real time.
+//! While waiting, the thread counts as asleep for the purposes of
+//! [auto-advance](crate::FakeClock::set_auto_advance).
+
+use crate::clock::TimerKey;
+use crate::FakeClock;
+use std::panic::Location;
+use std::sync::mpsc::{Receiver, RecvTimeoutError};
+use std::sync::{Condvar, LockResult, Mutex, MutexGuard, PoisonError};
+use std::thread::{self, Thread, ThreadId};
+use std::time::{Duration, Instant};
+
+/// The real-time interval at which waiting threads check the fake clock.
+pub const POLL_INTERVAL: Duration = Duration::from_millis(1);
+
+/// Waits for a value on `receiver` until the current fake clock has advanced
+/// by `timeout`, mirroring `Receiver::recv_timeout`.
+#[track_caller]
+pub fn recv_timeout<T>(receiver: &Receiver<T>, timeout: Duration) -> Result<T, RecvTimeoutError> {
+    let mut wait = FakeWait::new(timeout);
+    loop {
+        match receiver.recv_timeout(POLL_INTERVAL) {
+            Err(RecvTimeoutError::Timeout) if !wait.expired() => {}
+            result => return result,
+        }
+    }
+}
+
+/// Waits on `condvar` until notified or the current fake clock has advanced
+/// by `timeout`, mirroring `Condvar::wait_timeout`.
+///
+/// As with `Condvar::wait_timeout`, this may return early due to a spurious
+/// wakeup.
+#[track_caller]
+pub fn wait_timeout<'a, T>(
+    condvar: &Condvar,
+    mut guard: MutexGuard<'a, T>,
+    timeout: Duration,
+) -> LockResult<(MutexGuard<'a, T>, WaitTimeoutResult)> {
+    let mut wait = FakeWait::new(timeout);
+    loop {
+        let (next, result) = match condvar.wait_timeout(guard, POLL_INTERVAL) {
+            Ok((guard, result)) => (guard, Ok(result)),
+            Err(poisoned) => {
+                let (guard, result) = poisoned.into_inner();
+                (guard, Err(result))
+            }
+        };
+        guard = next;
+        let timed_out = match result {
+            Ok(result) | Err(result) if !result.timed_out() => false,
+            _ if wait.expired() => true,
+            _ => continue,
+        };
+        let output = (guard, WaitTimeoutResult(timed_out));
+        return match result {
+            Ok(_) => Ok(output),
+            Err(_) => Err(PoisonError::new(output)),
+        };
+    }
+}
+
+/// Threads unparked through [`unpark`] that have not yet returned from
+/// [`park_timeout`].
+static UNPARKED: Mutex<Vec<ThreadId>> = Mutex::new(Vec::new());
+
+/// Blocks the calling thread until it is unparked or the current fake clock
+/// has advanced by `timeout`, mirroring `std::thread::park_timeout`.
+///
+/// Wakeups are only reliable when the thread is unparked through [`unpark`].
+/// `Thread::unpark` is usually noticed too, but can be missed if the parked
+/// thread is scheduled a whole [`POLL_INTERVAL`] late.
+///
+/// As with `std::thread::park_timeout`, this may return early, e.g. when the
+/// thread's token was already available.
+#[track_caller]
+pub fn park_timeout(timeout: Duration) {
+    let mut wait = FakeWait::new(timeout);
+    let id = thread::current().id();
+    loop {
+        let start = Instant::now();
+        thread::park_timeout(POLL_INTERVAL);
+        let mut unparked = lock_unparked();
+        if let Some(index) = unparked.iter().position(|&unparked| unparked == id) {
+            unparked.swap_remove(index);
+            return;
+        }
+        drop(unparked);
+        // Returning before the real interval elapsed means we were most likely
+        // unparked; spurious wakeups are permitted anyway.
+        if start.elapsed() < POLL_INTERVAL || wait.expired() {
+            return;
+        }
+    }
+}
+
+/// Unparks `thread`, mirroring `Thread::unpark`, such that a
+/// [`park_timeout`] in progress or made later is guaranteed to return.
+pub fn unpark(thread: &Thread) {
+    let mut unparked = lock_unparked();
+    if !unparked.contains(&thread.id()) {
+        unparked.push(thread.id());
+    }
+    drop(unparked);
+    thread.unpark();
+}
+
+fn lock_unparked() -> MutexGuard<'static, Vec<ThreadId>> {
+    UNPARKED.lock().unwrap_or_else(PoisonError::into_inner)
+}
+
+/// Whether a wait returned by [`wait_timeout`] timed out, mirroring
+/// `std::sync::WaitTimeoutResult`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct WaitTimeoutResult(bool);
+
+impl WaitTimeoutResult {
+    /// Returns `true` if the wait timed out on the fake clock.
+    pub fn timed_out(&self) -> bool {
+        self.0
+    }
+}
+
+/// A fake-time deadline for a thread waiting on a real event.
+struct FakeWait {
+    clock: FakeClock,
+    deadline: Duration,
+    timer: Option<TimerKey>,
+    /// Where the wait started, to which auto-advance jumps are traced.
+    location: &'static Location<'static>,
+}
+
+impl FakeWait {
+    #[track_caller]
+    fn new(timeout: Duration) -> Self {
+        let clock = FakeClock::current();
+        let deadline = clock
+            .since_origin()
+            .checked_add(timeout)
+            .expect("overflow when computing timeout deadline");
+        Self {
+            clock,
+            deadline,
+            timer: None,
+            location: Location::caller(),
+        }
+    }
+
+    fn expired(&mut self) -> bool {
+        self.clock
+            .poll_blocking(self.deadline, &mut self.timer, self.location)
+    }
+}
+
+impl Drop for FakeWait {
+    fn drop(&mut self) {
+        self.clock.cancel_timer(&mut self.timer);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::FakeInstant;
+    use std::sync::mpsc;
+    use std::sync::{Arc, Mutex};
+
+    #[test]
+    fn test_recv_timeout() {
+        let clock = FakeClock::new();
+        let (tx, rx) = mpsc::channel();
+        let receiver = {
+            let clock = clock.clone();
+            thread::spawn(move || {
+                let _guard = clock.enter();
+                let first = recv_timeout(&rx, Duration::from_secs(3600));
+                let second = recv_timeout(&rx, Duration::from_secs(10));
+                (first, second)
+            })
+        };
+        tx.send(5).unwrap();
+        while clock.next_deadline().is_none() {
+            thread::yield_now();
+        }
+        clock.advance(Duration::from_secs(10));
+        let (first, second) = receiver.join().unwrap();
+        assert_eq!(Ok(5), first);
+        assert_eq!(Err(RecvTimeoutError::Timeout), second);
+        drop(tx);
+    }
+
+    #[test]
+    fn test_recv_timeout_disconnected() {
+        let _clock = FakeClock::new().enter();
+        let (tx, rx) = mpsc::channel::<()>();
+        drop(tx);
+        assert_eq!(
+            Err(RecvTimeoutError::Disconnected),
+            recv_timeout(&rx, Duration::from_secs(1))
+        );
+    }
+
+    #[test]
+    fn test_wait_timeout() {
+        let clock = FakeClock::new();
+        let pair = Arc::new((Mutex::new(false), Condvar::new()));
+        let waiter = {
+            let clock = clock.clone();
+            let pair = pair.clone();
+            thread::spawn(move || {
+                let _guard = clock.enter();
+                let (lock, condvar) = &*pair;
+                let guard = lock.lock().unwrap();
+                let (guard, result) = wait_timeout(condvar, guard, Duration::from_secs(5)).unwrap();
+                assert!(!*guard);
+                result.timed_out()
+            })
+        };
+        while clock.next_deadline().is_none() {
+            thread::yield_now();
+        }
+        clock.advance(Duration::from_secs(5));
+        assert!(waiter.join().unwrap());
+    }
+
+    #[test]
+    fn test_wait_timeout_notified() {
+        let _clock = FakeClock::new().enter();
+        let pair = Arc::new((Mutex::new(false), Condvar::new()));
+        let notifier = {
+            let pair = pair.clone();
+            thread::spawn(move || {
+                let (lock, condvar) = &*pair;
+                *lock.lock().unwrap() = true;
+                condvar.notify_all();
+            })
+        };
+        let (lock, condvar) = &*pair;
+        let mut guard = lock.lock().unwrap();
+        while !*guard {
+            let (next, result) = wait_timeout(condvar, guard, Duration::from_secs(1)).unwrap();
+            assert!(!result.timed_out());
+            guard = next;
+        }
+        notifier.join().unwrap();
+        assert_eq!(0, FakeInstant::time());
+    }
+
+    #[test]
+    fn test_park_timeout() {
+        let clock = FakeClock::new();
+        clock.set_auto_advance(true);
+        let parked = {
+            let clock = clock.clone();
+            thread::spawn(move || {
+                let _participant = clock.participate();
+                let _guard = clock.enter();
+                park_timeout(Duration::from_secs(30));
+                FakeInstant::time()
+            })
+        };
+        assert_eq!(30_000, parked.join().unwrap());
+    }
+
+    #[test]
+    fn test_unpark_after_token_taken() {
+        let _clock = FakeClock::new().enter();
+        unpark(&thread::current());
+        // Take the `std` token, as if the unpark happened while this thread
+        // was scheduled late.
+        thread::park();
+        park_timeout(Duration::from_secs(3600));
+        assert_eq!(0, FakeInstant::time());
+    }
+}
@@ -0,0 +1,203 @@
+// Copyright 2018 MaidSafe.net limited.
+//
+// This SAFE Network Software is licensed to you under the MIT license <LICENSE-MIT
+// http://opensource.org/licenses/MIT> or the Modified BSD license <LICENSE-BSD
+// https://opensource.org/licenses/BSD-3-Clause>, at your option. This file may not be copied,
+// modified, or distributed except according to those terms. Please review the Licences for the
+// specific language governing permissions and limitations relating to use of the SAFE Network
+// Software.
+
+//! A fake counterpart to `std::time::SystemTime`.
+
+use crate::clock::{self, FakeClock};
+use std::error::Error;
+use std::fmt;
+use std::ops::{Add, AddAssign, Sub, SubAssign};
+use std::panic::Location;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// Struct representing a fake wall-clock time, mimicking
+/// `std::time::SystemTime`.
+///
+/// A `FakeSystemTime` is derived from a clock's fake monotonic time plus that
+/// clock's [epoch offset](FakeSystemTime::set_epoch_offset): the wall-clock
+/// time at which the monotonic fake time was zero. The offset defaults to
+/// zero, so a fresh clock reads `UNIX_EPOCH`.
+///
+/// Unlike `SystemTime`, a `FakeSystemTime` cannot represent times before
+/// `UNIX_EPOCH`.
+#[derive(Clone, Copy, Debug, Hash, PartialEq, Eq, PartialOrd, Ord)]
+pub struct FakeSystemTime {
+    since_epoch: Duration,
+}
+
+impl FakeSystemTime {
+    /// An anchor in time which corresponds to "1970-01-01 00:00:00 UTC",
+    /// mirroring `SystemTime::UNIX_EPOCH`.
+    pub const UNIX_EPOCH: Self = Self {
+        since_epoch: Duration::ZERO,
+    };
+
+    /// Returns a `FakeSystemTime` representing the current fake wall-clock
+    /// time.
+    #[track_caller]
+    pub fn now() -> Self {
+        let location = Location::caller();
+        clock::with_current(|clock| clock.traced_system_now(location))
+    }
+
+    /// Sets the wall-clock time corresponding to a fake time of zero on the
+    /// current clock, as a duration since `UNIX_EPOCH`. Returns the old
+    /// offset.
+    pub fn set_epoch_offset(offset: Duration) -> Duration {
+        clock::with_current(|clock| clock.set_epoch_offset(offset))
+    }
+
+    /// Returns the current clock's epoch offset.
+    pub fn epoch_offset() -> Duration {
+        clock::with_current(FakeClock::epoch_offset)
+    }
+
+    pub(crate) fn from_since_epoch(since_epoch: Duration) -> Self {
+        Self { since_epoch }
+    }
+
+    /// Returns the amount of fake time elapsed from `earlier` to `self`.
+    ///
+    /// Returns an error holding the difference if `earlier` is later than
+    /// `self`.
+    pub fn duration_since(&self, earlier: Self) -> Result<Duration, FakeSystemTimeError> {
+        self.since_epoch
+            .checked_sub(earlier.since_epoch)
+            .ok_or_else(|| FakeSystemTimeError(earlier.since_epoch - self.since_epoch))
+    }
+
+    /// Returns the fake time elapsed since `self` was created.
+    ///
+    /// Returns an error holding the difference if the current fake wall-clock
+    /// time is earlier than `self`.
+    #[track_caller]
+    pub fn elapsed(&self) -> Result<Duration, FakeSystemTimeError> {
+        Self::now().duration_since(*self)
+    }
+
+    /// Returns `Some(t)` where `t` is the time `self + duration` if `t` can be
+    /// represented as `FakeSystemTime`, `None` otherwise.
+    pub fn checked_add(&self, duration: Duration) -> Option<Self> {
+        self.since_epoch
+            .checked_add(duration)
+            .map(Self::from_since_epoch)
+    }
+
+    /// Returns `Some(t)` where `t` is the time `self - duration` if `t` can be
+    /// represented as `FakeSystemTime`, `None` otherwise.
+    pub fn checked_sub(&self, duration: Duration) -> Option<Self> {
+        self.since_epoch
+            .checked_sub(duration)
+            .map(Self::from_since_epoch)
+    }
+}
+
+impl Add<Duration> for FakeSystemTime {
+    type Output = Self;
+    fn add(self, other: Duration) -> Self {
+        self.checked_add(other)
+            .expect("overflow when adding duration to system time")
+    }
+}
+
+impl AddAssign<Duration> for FakeSystemTime {
+    fn add_assign(&mut self, rhs: Duration) {
+        *self = *self + rhs;
+    }
+}
+
+impl Sub<Duration> for FakeSystemTime {
+    type Output = Self;
+    fn sub(self, other: Duration) -> Self {
+        self.checked_sub(other)
+            .expect("overflow when subtracting duration from system time")
+    }
+}
+
+impl SubAssign<Duration> for FakeSystemTime {
+    fn sub_assign(&mut self, rhs: Duration) {
+        *self = *self - rhs;
+    }
+}
+
+impl From<FakeSystemTime> for SystemTime {
+    fn from(time: FakeSystemTime) -> Self {
+        UNIX_EPOCH + time.since_epoch
+    }
+}
+
+/// An error returned from `FakeSystemTime::duration_since` and
+/// `FakeSystemTime::elapsed` when the second time is later than the first,
+/// mirroring `std::time::SystemTimeError`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct FakeSystemTimeError(Duration);
+
+impl FakeSystemTimeError {
+    /// Returns how far the second time was ahead of the first.
+    pub fn duration(&self) -> Duration {
+        self.0
+    }
+}
+
+impl fmt::Display for FakeSystemTimeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "second time provided was later than self")
+    }
+}
+
+impl Error for FakeSystemTimeError {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::FakeInstant;
+
+    #[test]
+    fn test_epoch_offset() {
+        let _clock = FakeClock::new().enter();
+        const OFFSET: Duration = Duration::from_secs(1_700_000_000);
+        FakeInstant::set_time(1500);
+        assert_eq!(Duration::ZERO, FakeSystemTime::set_epoch_offset(OFFSET));
+        let now = FakeSystemTime::now();
+        assert_eq!(
+            Ok(OFFSET + Duration::from_millis(1500)),
+            now.duration_since(FakeSystemTime::UNIX_EPOCH)
+        );
+        assert_eq!(
+            UNIX_EPOCH + OFFSET + Duration::from_millis(1500),
+            SystemTime::from(now)
+        );
+    }
+
+    #[test]
+    fn test_elapsed() {
+        let _clock = FakeClock::new().enter();
+        let time = FakeSystemTime::now();
+        FakeInstant::advance_time(250);
+        assert_eq!(Ok(Duration::from_millis(250)), time.elapsed());
+    }
+
+    #[test]
+    fn test_duration_since_err() {
+        let earlier = FakeSystemTime::UNIX_EPOCH + Duration::from_secs(1);
+        let later = earlier + Duration::from_millis(300);
+        let err = earlier.duration_since(later).unwrap_err();
+        assert_eq!(Duration::from_millis(300), err.duration());
+    }
+
+    #[test]
+    fn test_checked_sub_before_epoch() {
+        let time = FakeSystemTime::UNIX_EPOCH + Duration::from_secs(1);
+        assert_eq!(
+            Some(FakeSystemTime::UNIX_EPOCH),
+            time.checked_sub(Duration::from_secs(1))
+        );
+        assert_eq!(None, time.checked_sub(Duration::from_secs(2)));
+    }
+}
@@ -0,0 +1,149 @@
+// Copyright 2018 MaidSafe.net limited.
+//
+// This SAFE Network Software is licensed to you under the MIT license <LICENSE-MIT
+// http://opensource.org/licenses/MIT> or the Modified BSD license <LICENSE-BSD
+// https://opensource.org/licenses/BSD-3-Clause>, at your option. This file may not be copied,
+// modified, or distributed except according to those terms. Please review the Licences for the
+// specific language governing permissions and limitations relating to use of the SAFE Network
+// Software.
+
+//! Thread spawning that carries the current fake clock into new threads.
+//!
+//! Threads spawned with `std::thread` start on their own thread-local clock,
+//! at zero. The wrappers in this module instead make the spawning thread's
+//! [current clock](FakeClock::current) current in the new thread too, so both
+//! see the same, shared fake time.
+
+use crate::FakeClock;
+use std::thread::{JoinHandle, ScopedJoinHandle};
+
+/// Spawns a new thread sharing the calling thread's current fake clock,
+/// mirroring `std::thread::spawn`.
+pub fn spawn<F, T>(f: F) -> JoinHandle<T>
+where
+    F: FnOnce() -> T + Send + 'static,
+    T: Send + 'static,
+{
+    std::thread::spawn(wrap(f))
+}
+
+/// Creates a scope for spawning scoped threads sharing the calling thread's
+/// current fake clock, mirroring `std::thread::scope`.
+pub fn scope<'env, F, T>(f: F) -> T
+where
+    F: for<'scope> FnOnce(&Scope<'scope, 'env>) -> T,
+{
+    let clock = FakeClock::current();
+    std::thread::scope(|inner| f(&Scope { inner, clock }))
+}
+
+/// Wraps `f` so that it runs with the calling thread's current fake clock,
+/// whichever thread it ends up running on. Useful for submitting jobs to a
+/// thread pool.
+pub fn wrap<F, T>(f: F) -> impl FnOnce() -> T + Send + 'static
+where
+    F: FnOnce() -> T + Send + 'static,
+{
+    let clock = FakeClock::current();
+    move || {
+        let _guard = clock.enter();
+        f()
+    }
+}
+
+/// Returns a hook that [installs](FakeClock::install) the calling thread's
+/// current fake clock on whichever thread calls it.
+///
+/// Call the hook from a thread pool's thread start handler so that every
+/// worker shares the clock, e.g. for `rayon`:
+///
+/// ```ignore
+/// let inherit = fake_instant::thread::inherit();
+/// let pool = rayon::ThreadPoolBuilder::new()
+///     .start_handler(move |_| inherit())
+///     .build()?;
+/// ```
+pub fn inherit() -> impl Fn() + Clone + Send + Sync + 'static {
+    let clock = FakeClock::current();
+    move || clock.install()
+}
+
+/// A scope for spawning threads sharing a fake clock, created by [`scope`].
+///
+/// `Scope` is `Clone`; move a clone into a spawned thread to spawn further
+/// threads from it.
+#[derive(Clone, Debug)]
+pub struct Scope<'scope, 'env: 'scope> {
+    inner: &'scope std::thread::Scope<'scope, 'env>,
+    clock: FakeClock,
+}
+
+impl<'scope, 'env> Scope<'scope, 'env> {
+    /// Spawns a scoped thread sharing this scope's fake clock, mirroring
+    /// `std::thread::Scope::spawn`.
+    pub fn spawn<F, T>(&self, f: F) -> ScopedJoinHandle<'scope, T>
+    where
+        F: FnOnce() -> T + Send + 'scope,
+        T: Send + 'scope,
+    {
+        let clock = self.clock.clone();
+        self.inner.spawn(move || {
+            let _guard = clock.enter();
+            f()
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::FakeInstant;
+    use std::time::Duration;
+
+    #[test]
+    fn test_spawn() {
+        let _clock = FakeClock::new().enter();
+        FakeInstant::set_time(200);
+        let inst = FakeInstant::now();
+        spawn(move || {
+            assert_eq!(200, FakeInstant::time());
+            FakeInstant::advance_time(300);
+            assert_eq!(Duration::from_millis(300), inst.elapsed());
+        })
+        .join()
+        .unwrap();
+        assert_eq!(500, FakeInstant::time());
+    }
+
+    #[test]
+    fn test_scope() {
+        let clock = FakeClock::new();
+        let _guard = clock.enter();
+        scope(|s| {
+            let nested = s.clone();
+            s.spawn(move || {
+                FakeInstant::advance_time(10);
+                nested.spawn(|| FakeInstant::advance_time(20));
+            });
+        });
+        assert_eq!(30, FakeInstant::time());
+    }
+
+    #[test]
+    fn test_inherit() {
+        let clock = FakeClock::new();
+        clock.set(Duration::from_secs(9));
+        let inherit = {
+            let _guard = clock.enter();
+            inherit()
+        };
+        std::thread::spawn(move || {
+            inherit();
+            FakeInstant::advance_time(1);
+            assert_eq!(9001, FakeInstant::time());
+        })
+        .join()
+        .unwrap();
+        assert_eq!(Duration::from_millis(9001), clock.since_origin());
+    }
+}
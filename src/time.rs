@@ -0,0 +1,81 @@
+// Copyright 2018 MaidSafe.net limited.
+//
+// This SAFE Network Software is licensed to you under the MIT license <LICENSE-MIT
+// http://opensource.org/licenses/MIT> or the Modified BSD license <LICENSE-BSD
+// https://opensource.org/licenses/BSD-3-Clause>, at your option. This file may not be copied,
+// modified, or distributed except according to those terms. Please review the Licences for the
+// specific language governing permissions and limitations relating to use of the SAFE Network
+// Software.
+
+//! Drop-in replacements for `std::time`, switched by the `fake` feature.
+//!
+//! With the `fake` feature enabled, the types in this module are the fake
+//! ones from this crate; otherwise they are re-exports of the real `std` types.
+//! Code importing `fake_instant::time::{Instant, SystemTime, sleep}` can then
+//! enable the feature only for tests, e.g. through a dev-dependency:
+//!
+//! ```toml
+//! [dependencies]
+//! fake_instant = "0.5"
+//!
+//! [dev-dependencies]
+//! fake_instant = { version = "0.5", features = ["fake"] }
+//! ```
+
+pub use std::time::Duration;
+
+#[cfg(feature = "fake")]
+pub use crate::{
+    sleep, FakeInstant as Instant, FakeSystemTime as SystemTime,
+    FakeSystemTimeError as SystemTimeError,
+};
+
+/// An anchor in time which corresponds to "1970-01-01 00:00:00 UTC".
+#[cfg(feature = "fake")]
+pub const UNIX_EPOCH: SystemTime = SystemTime::UNIX_EPOCH;
+
+#[cfg(not(feature = "fake"))]
+pub use std::thread::sleep;
+#[cfg(not(feature = "fake"))]
+pub use std::time::{Instant, SystemTime, SystemTimeError, UNIX_EPOCH};
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_same_api() {
+        let start = Instant::now();
+        sleep(Duration::ZERO);
+        let later = start + Duration::from_nanos(1);
+        assert_eq!(Duration::from_nanos(1), later.duration_since(start));
+        assert!(start.elapsed() < Duration::from_secs(3600));
+
+        let wall = SystemTime::now();
+        let since_epoch: Result<Duration, SystemTimeError> = wall.duration_since(UNIX_EPOCH);
+        assert!(since_epoch.is_ok());
+        assert!(wall.checked_add(Duration::from_secs(1)).is_some());
+    }
+
+    #[test]
+    fn test_same_signatures() {
+        let start = Instant::now();
+        let later = start + Duration::from_nanos(1);
+        assert_eq!(
+            Duration::from_nanos(1),
+            Instant::duration_since(&later, start)
+        );
+        let _: fn(&Instant, Instant) -> Option<Duration> = Instant::checked_duration_since;
+        let _: fn(&Instant, Instant) -> Duration = Instant::saturating_duration_since;
+        let _: fn(&Instant, Duration) -> Option<Instant> = Instant::checked_add;
+        let _: fn(&Instant, Duration) -> Option<Instant> = Instant::checked_sub;
+        let elapsed: fn(&Instant) -> Duration = Instant::elapsed;
+        assert!(elapsed(&start) < Duration::from_secs(3600));
+
+        let _: fn(&SystemTime, SystemTime) -> Result<Duration, SystemTimeError> =
+            SystemTime::duration_since;
+        let _: fn(&SystemTime) -> Result<Duration, SystemTimeError> = SystemTime::elapsed;
+        let _: fn(&SystemTime, Duration) -> Option<SystemTime> = SystemTime::checked_add;
+        let _: fn(&SystemTime, Duration) -> Option<SystemTime> = SystemTime::checked_sub;
+    }
+}
@@ -0,0 +1,174 @@
+// Copyright 2018 MaidSafe.net limited.
+//
+// This SAFE Network Software is licensed to you under the MIT license <LICENSE-MIT
+// http://opensource.org/licenses/MIT> or the Modified BSD license <LICENSE-BSD
+// https://opensource.org/licenses/BSD-3-Clause>, at your option. This file may not be copied,
+// modified, or distributed except according to those terms. Please review the Licences for the
+// specific language governing permissions and limitations relating to use of the SAFE Network
+// Software.
+
+//! Interop with tokio's paused test clock, enabled by the `tokio` feature.
+//!
+//! [`lockstep`] forwards every change of a fake clock's time to tokio's
+//! clock, so that `tokio::time` timers observe the fake time, and
+//! [`FakeInstant::to_tokio`] and [`FakeInstant::from_tokio`] convert between
+//! the two kinds of instants.
+
+use crate::{FakeClock, FakeInstant, HookId};
+use std::future::Future;
+use std::pin::pin;
+use std::task::{Context, Waker};
+use tokio::runtime::Handle;
+use tokio::time::Instant;
+
+/// Keeps the current tokio runtime's paused clock in step with `clock` until
+/// the returned guard is dropped.
+///
+/// Tokio's clock is anchored so that its current instant corresponds to
+/// `clock`'s current fake time; [`Lockstep::origin`] returns the tokio
+/// instant corresponding to [`FakeInstant::ORIGIN`]. Whenever `clock` is set,
+/// advanced, restored or jumps to a pending deadline, tokio's clock is
+/// advanced to match, and tokio timers that are now due fire the next time
+/// the runtime polls them. Tokio's clock cannot
+/// move backwards, so it stays put while the fake time is behind it and
+/// catches up once the fake time passes it again.
+///
+/// Tokio's clock must be paused, with `#[tokio::test(start_paused = true)]`
+/// or `tokio::time::pause`. Only this direction is kept in step: tokio's
+/// auto-advance moves its own clock without moving `clock`.
+///
+/// # Panics
+///
+/// Panics if called outside of a tokio runtime, or if `clock`'s fake time is
+/// too far ahead of tokio's clock to anchor them. Changing the fake time
+/// panics while tokio's clock is not paused.
+pub fn lockstep(clock: &FakeClock) -> Lockstep {
+    let handle = Handle::try_current().expect("`lockstep` called outside of a tokio runtime");
+    let origin = Instant::now()
+        .checked_sub(clock.since_origin())
+        .expect("fake time is too far ahead of tokio's clock to anchor it");
+    let forward = |handle: Handle| {
+        move |_old, new| advance_to(&handle, (FakeInstant::ORIGIN + new).to_tokio(origin))
+    };
+    let hooks = [
+        clock.on_set(forward(handle.clone())),
+        clock.on_advance(forward(handle)),
+    ];
+    Lockstep {
+        clock: clock.clone(),
+        origin,
+        hooks,
+    }
+}
+
+/// Keeps the current tokio runtime's paused clock in step with the current
+/// fake clock until the returned guard is dropped. See [`lockstep`].
+pub fn lockstep_current() -> Lockstep {
+    lockstep(&FakeClock::current())
+}
+
+/// Advances the paused clock of `handle`'s runtime to `target`, unless it is
+/// already there.
+fn advance_to(handle: &Handle, target: Instant) {
+    let _enter = handle.enter();
+    let now = Instant::now();
+    if target > now {
+        // `advance` moves tokio's clock when first polled, then yields to the
+        // runtime, which there is no need to wait for.
+        let advance = pin!(tokio::time::advance(target - now));
+        let _ = advance.poll(&mut Context::from_waker(Waker::noop()));
+    }
+}
+
+/// Guard returned by [`lockstep`] and [`lockstep_current`], keeping tokio's
+/// clock in step with a fake clock until dropped.
+#[must_use = "tokio's clock stops following the fake clock as soon as the guard is dropped"]
+#[derive(Debug)]
+pub struct Lockstep {
+    clock: FakeClock,
+    origin: Instant,
+    hooks: [HookId; 2],
+}
+
+impl Lockstep {
+    /// Returns the tokio instant corresponding to [`FakeInstant::ORIGIN`], for
+    /// use with [`FakeInstant::to_tokio`] and [`FakeInstant::from_tokio`].
+    pub fn origin(&self) -> Instant {
+        self.origin
+    }
+}
+
+impl Drop for Lockstep {
+    fn drop(&mut self) {
+        for id in self.hooks {
+            self.clock.remove_hook(id);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+    use tokio::runtime::{Builder, Runtime};
+
+    fn paused_runtime() -> Runtime {
+        Builder::new_current_thread()
+            .enable_time()
+            .start_paused(true)
+            .build()
+            .unwrap()
+    }
+
+    #[test]
+    fn test_lockstep() {
+        let _clock = FakeClock::new().enter();
+        paused_runtime().block_on(async {
+            FakeInstant::set_time(100);
+            let lockstep = lockstep_current();
+            let origin = lockstep.origin();
+            assert_eq!(FakeInstant::now().to_tokio(origin), Instant::now());
+
+            FakeInstant::advance_time(1500);
+            assert_eq!(origin + Duration::from_millis(1600), Instant::now());
+            FakeInstant::set_time(4000);
+            assert_eq!(origin + Duration::from_secs(4), Instant::now());
+            FakeInstant::set_time(1000);
+            assert_eq!(origin + Duration::from_secs(4), Instant::now());
+            FakeInstant::set_time(5000);
+            assert_eq!(
+                FakeInstant::now(),
+                FakeInstant::from_tokio(Instant::now(), origin)
+            );
+
+            drop(lockstep);
+            FakeInstant::advance_time(1000);
+            assert_eq!(origin + Duration::from_secs(5), Instant::now());
+        });
+    }
+
+    #[test]
+    fn test_lockstep_wakes_timers() {
+        let clock = FakeClock::new();
+        paused_runtime().block_on(async {
+            let lockstep = lockstep(&clock);
+            let sleep = tokio::spawn(tokio::time::sleep(Duration::from_secs(2)));
+            for _ in 0..10 {
+                tokio::task::yield_now().await;
+            }
+            assert!(!sleep.is_finished());
+            clock.advance(Duration::from_secs(2));
+            for _ in 0..10 {
+                tokio::task::yield_now().await;
+            }
+            assert!(sleep.is_finished());
+            assert_eq!(lockstep.origin() + Duration::from_secs(2), Instant::now());
+        });
+    }
+
+    #[test]
+    #[should_panic(expected = "outside of a tokio runtime")]
+    fn test_lockstep_outside_runtime() {
+        drop(lockstep(&FakeClock::new()));
+    }
+}
@@ -0,0 +1,205 @@
+// Copyright 2018 MaidSafe.net limited.
+//
+// This SAFE Network Software is licensed to you under the MIT license <LICENSE-MIT
+// http://opensource.org/licenses/MIT> or the Modified BSD license <LICENSE-BSD
+// https://opensource.org/licenses/BSD-3-Clause>, at your option. This file may not be copied,
+// modified, or distributed except according to those terms. Please review the Licences for the
+// specific language governing permissions and limitations relating to use of the SAFE Network
+// Software.
+
+//! Traits abstracting over real and fake time.
+
+use crate::{FakeClock, FakeInstant};
+use std::fmt::Debug;
+use std::hash::Hash;
+use std::ops::{Add, AddAssign, Sub, SubAssign};
+use std::time::{Duration, Instant};
+
+/// A source of monotonic time, allowing code to be written once against
+/// either the real clock ([`RealClock`]) or a fake one ([`FakeClock`]).
+pub trait Clock {
+    /// The instant type produced by this clock.
+    type Instant: ClockInstant;
+
+    /// Returns an instant representing this clock's current time.
+    fn now(&self) -> Self::Instant;
+
+    /// Returns the time elapsed on this clock since `instant`, or zero if
+    /// `instant` is in the future. For a [`FakeClock`] the clock's
+    /// [`MonotonicPolicy`](crate::MonotonicPolicy) decides instead, so a
+    /// future `instant` panics under `Strict`.
+    fn elapsed(&self, instant: Self::Instant) -> Duration {
+        self.now().saturating_duration_since(instant)
+    }
+
+    /// Blocks the calling thread until this clock has advanced by at least
+    /// `duration`.
+    fn sleep(&self, duration: Duration);
+}
+
+/// The method set shared by `std::time::Instant` and [`FakeInstant`].
+pub trait ClockInstant:
+    Copy
+    + Debug
+    + Hash
+    + Ord
+    + Send
+    + Sync
+    + 'static
+    + Add<Duration, Output = Self>
+    + AddAssign<Duration>
+    + Sub<Duration, Output = Self>
+    + SubAssign<Duration>
+    + Sub<Self, Output = Duration>
+{
+    /// Returns the duration that passed between `self` and `earlier`, or zero
+    /// if `earlier` is later than `self`. For a [`FakeInstant`] the current
+    /// clock's [`MonotonicPolicy`](crate::MonotonicPolicy) decides instead, so
+    /// a later `earlier` panics under `Strict`.
+    fn duration_since(&self, earlier: Self) -> Duration;
+
+    /// Returns the duration that passed between `self` and `earlier`, or
+    /// `None` if `earlier` is later than `self`.
+    fn checked_duration_since(&self, earlier: Self) -> Option<Duration>;
+
+    /// Returns the duration that passed between `self` and `earlier`, or zero
+    /// if `earlier` is later than `self`.
+    fn saturating_duration_since(&self, earlier: Self) -> Duration;
+
+    /// Returns `self + duration`, or `None` if it cannot be represented.
+    fn checked_add(&self, duration: Duration) -> Option<Self>;
+
+    /// Returns `self - duration`, or `None` if it cannot be represented.
+    fn checked_sub(&self, duration: Duration) -> Option<Self>;
+}
+
+/// A [`Clock`] backed by `std::time::Instant` and `std::thread::sleep`.
+#[derive(Clone, Copy, Debug, Default, Hash, PartialEq, Eq)]
+pub struct RealClock;
+
+impl Clock for RealClock {
+    type Instant = Instant;
+
+    fn now(&self) -> Instant {
+        Instant::now()
+    }
+
+    fn sleep(&self, duration: Duration) {
+        std::thread::sleep(duration)
+    }
+}
+
+impl Clock for FakeClock {
+    type Instant = FakeInstant;
+
+    fn now(&self) -> FakeInstant {
+        FakeClock::now(self)
+    }
+
+    fn elapsed(&self, instant: FakeInstant) -> Duration {
+        FakeClock::elapsed(self, instant)
+    }
+
+    fn sleep(&self, duration: Duration) {
+        FakeClock::sleep(self, duration)
+    }
+}
+
+impl ClockInstant for Instant {
+    fn duration_since(&self, earlier: Self) -> Duration {
+        Instant::duration_since(self, earlier)
+    }
+
+    fn checked_duration_since(&self, earlier: Self) -> Option<Duration> {
+        Instant::checked_duration_since(self, earlier)
+    }
+
+    fn saturating_duration_since(&self, earlier: Self) -> Duration {
+        Instant::saturating_duration_since(self, earlier)
+    }
+
+    fn checked_add(&self, duration: Duration) -> Option<Self> {
+        Instant::checked_add(self, duration)
+    }
+
+    fn checked_sub(&self, duration: Duration) -> Option<Self> {
+        Instant::checked_sub(self, duration)
+    }
+}
+
+impl ClockInstant for FakeInstant {
+    fn duration_since(&self, earlier: Self) -> Duration {
+        FakeInstant::duration_since(self, earlier)
+    }
+
+    fn checked_duration_since(&self, earlier: Self) -> Option<Duration> {
+        FakeInstant::checked_duration_since(self, earlier)
+    }
+
+    fn saturating_duration_since(&self, earlier: Self) -> Duration {
+        FakeInstant::saturating_duration_since(self, earlier)
+    }
+
+    fn checked_add(&self, duration: Duration) -> Option<Self> {
+        FakeInstant::checked_add(self, duration)
+    }
+
+    fn checked_sub(&self, duration: Duration) -> Option<Self> {
+        FakeInstant::checked_sub(self, duration)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A rate limiter written once against `Clock`.
+    struct Limiter<C: Clock> {
+        clock: C,
+        last: Option<C::Instant>,
+        period: Duration,
+    }
+
+    impl<C: Clock> Limiter<C> {
+        fn try_acquire(&mut self) -> bool {
+            let now = self.clock.now();
+            match self.last {
+                Some(last) if now.duration_since(last) < self.period => false,
+                _ => {
+                    self.last = Some(now);
+                    true
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_fake_clock() {
+        let clock = FakeClock::new();
+        let mut limiter = Limiter {
+            clock: clock.clone(),
+            last: None,
+            period: Duration::from_secs(1),
+        };
+        assert!(limiter.try_acquire());
+        assert!(!limiter.try_acquire());
+        clock.advance(Duration::from_millis(999));
+        assert!(!limiter.try_acquire());
+        clock.advance(Duration::from_millis(1));
+        assert!(limiter.try_acquire());
+    }
+
+    #[test]
+    fn test_real_clock() {
+        let mut limiter = Limiter {
+            clock: RealClock,
+            last: None,
+            period: Duration::from_secs(3600),
+        };
+        assert!(limiter.try_acquire());
+        assert!(!limiter.try_acquire());
+        let start = RealClock.now();
+        RealClock.sleep(Duration::from_millis(1));
+        assert!(RealClock.elapsed(start) >= Duration::from_millis(1));
+    }
+}
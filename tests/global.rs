@@ -0,0 +1,41 @@
+//! Tests for `ClockMode::Global`. These live in their own test binary since
+//! the clock mode is process-wide and would leak into the unit tests.
+
+use fake_instant::{ClockMode, FakeInstant};
+use std::sync::Mutex;
+use std::time::Duration;
+
+/// Serializes the tests in this file, which all share the global fake time.
+static LOCK: Mutex<()> = Mutex::new(());
+
+#[test]
+fn test_global_threads() {
+    let _lock = LOCK.lock().unwrap();
+    FakeInstant::set_clock_mode(ClockMode::Global);
+    FakeInstant::set_time(200);
+    let inst1 = FakeInstant::now();
+    std::thread::spawn(move || {
+        assert_eq!(200, FakeInstant::time());
+        FakeInstant::advance_time(300);
+        assert_eq!(Duration::from_millis(300), inst1.elapsed());
+    })
+    .join()
+    .unwrap();
+    assert_eq!(500, FakeInstant::time());
+    assert_eq!(Duration::from_millis(300), inst1.elapsed());
+}
+
+#[test]
+fn test_switch_modes() {
+    let _lock = LOCK.lock().unwrap();
+    FakeInstant::set_clock_mode(ClockMode::Global);
+    FakeInstant::set_time(1000);
+    assert_eq!(
+        ClockMode::Global,
+        FakeInstant::set_clock_mode(ClockMode::ThreadLocal)
+    );
+    assert_eq!(ClockMode::ThreadLocal, FakeInstant::clock_mode());
+    assert_eq!(0, FakeInstant::time());
+    FakeInstant::set_clock_mode(ClockMode::Global);
+    assert_eq!(1000, FakeInstant::time());
+}